@@ -0,0 +1,126 @@
+//! Compares block-at-a-time decoding through `FilterState` with the frame-at-once
+//! `FrameDecoder::decode_frame` pipeline over `FrameFilterState`, and the scalar synthesis
+//! of `FilterState` with the SIMD one of `SimdFilterState`.
+//!
+//! Run with `cargo bench --bench synthesis`, and with
+//! `RUSTFLAGS="-C target-cpu=native" cargo bench --bench synthesis` for AVX2.
+
+use mini_sbc::filter_state::FilterState;
+use mini_sbc::filter_state::SynthesisFilter;
+use mini_sbc::frame_decoder::FrameDecoder;
+use mini_sbc::frame_filter_state::FrameFilterState;
+use mini_sbc::header::{
+    AllocationMethod, Blocks, ChannelMode, Frequency, SBCHeader, Subbands, MAX_BLOCKS,
+};
+use mini_sbc::sbc::{Channels, ValidChannels, ValidSubbands};
+use mini_sbc::simd_filter_state::SimdFilterState;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const FRAMES: usize = 20_000;
+
+/// A joint stereo 8 subband 16 block frame with moderate scale factors and a pseudo-random
+/// payload. The CRC is skipped while decoding.
+fn frame() -> (SBCHeader, Vec<u8>) {
+    let h = SBCHeader::SBC {
+        frequency: Frequency::SBC_FREQ_44100,
+        blocks: Blocks::SBC_BLK_16,
+        channel_mode: ChannelMode::SBC_MODE_JOINT_STEREO,
+        allocation_method: AllocationMethod::SBC_AM_LOUDNESS,
+        subbands: Subbands::SBC_SB_8,
+        bitpool: 53,
+    };
+    let mut data = vec![0x00, 0x5A];
+    data.extend_from_slice(&[0x98, 0x76, 0x65, 0x54, 0x98, 0x76, 0x65, 0x54]);
+    let mut seed = 0x1234_5678_u32;
+    for _ in 0..256 {
+        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+        data.push((seed >> 24) as u8);
+    }
+    (h, data)
+}
+
+fn run(name: &str, mut f: impl FnMut()) -> Duration {
+    for _ in 0..FRAMES / 10 {
+        f();
+    }
+    let start = Instant::now();
+    for _ in 0..FRAMES {
+        f();
+    }
+    let t = start.elapsed();
+    println!(
+        "{:<8} {:>10.1} ns/frame",
+        name,
+        t.as_nanos() as f64 / FRAMES as f64
+    );
+    t
+}
+
+/// Synthesis alone, over the same pseudo-random subband samples as a frame of 16 blocks.
+fn synthesis<const CHANNELS: usize, const SUBBANDS: usize>(
+    name: &str,
+    mut filter: impl SynthesisFilter<CHANNELS, SUBBANDS>,
+) -> Duration
+where
+    Channels<CHANNELS>: ValidChannels,
+    mini_sbc::sbc::Subbands<SUBBANDS>: ValidSubbands,
+{
+    let mut seed = 0x1234_5678_u32;
+    let mut s = [[[0; SUBBANDS]; CHANNELS]; MAX_BLOCKS];
+    for s in s.iter_mut().flatten().flatten() {
+        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+        *s = (seed as i32) >> 17;
+    }
+    run(name, || {
+        for s in &s {
+            black_box(filter.filter(black_box(s)));
+        }
+    })
+}
+
+fn main() {
+    let (h, data) = frame();
+
+    let mut block_state = FilterState::<2, 8>::new();
+    let block = run("block", || {
+        let input = &mut &data[..];
+        let frame = FrameDecoder::new_skip_crc(&h, &mut block_state, input).unwrap();
+        for x in frame {
+            black_box(x);
+        }
+    });
+
+    let mut frame_state = FrameFilterState::<2, 8>::new();
+    let mut o = [[[0; 8]; 2]; MAX_BLOCKS];
+    let frame = run("frame", || {
+        let input = &mut &data[..];
+        let mut frame = FrameDecoder::new_skip_crc(&h, &mut frame_state, input).unwrap();
+        black_box(frame.decode_frame(&mut o).unwrap());
+        black_box(&o);
+    });
+
+    println!(
+        "speedup  {:>10.2}x",
+        block.as_secs_f64() / frame.as_secs_f64()
+    );
+
+    for (name, scalar, simd) in [
+        (
+            "2x8",
+            synthesis("scalar", FilterState::<2, 8>::new()),
+            synthesis("simd", SimdFilterState::<2, 8>::new()),
+        ),
+        (
+            "1x4",
+            synthesis("scalar", FilterState::<1, 4>::new()),
+            synthesis("simd", SimdFilterState::<1, 4>::new()),
+        ),
+    ] {
+        println!(
+            "simd {}  {:>10.2}x",
+            name,
+            scalar.as_secs_f64() / simd.as_secs_f64()
+        );
+    }
+}
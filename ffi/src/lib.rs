@@ -0,0 +1,522 @@
+//! A C library with the API and behaviour of libsbc, so that C code linking libsbc can link
+//! this instead. The declarations are in `include/sbc/sbc.h`.
+//!
+//! As in libsbc, the first frame parsed or decoded sets the fields of `sbc_t`, and the first
+//! frame encoded takes its configuration from them. After that only the bitpool follows the
+//! fields.
+
+#![allow(non_camel_case_types, clippy::missing_safety_doc)]
+
+use core::ffi::{c_char, c_int, c_uint, c_ulong, c_void};
+use core::slice;
+
+use libc::{size_t, ssize_t, EINVAL, EIO, ENOSPC};
+use mini_sbc::encoder::Encoder;
+use mini_sbc::filter_state::FilterState;
+use mini_sbc::frame_decoder::{FrameDecodeError, FrameDecoder, FrameReader};
+use mini_sbc::header::{
+    AllocationMethod, Blocks, ChannelMode, Frequency, SBCHeader, Subbands, MAX_BLOCKS, MSBC_BLOCKS,
+    MSBC_SYNCWORD, SBC_SYNCWORD,
+};
+use mini_sbc::sbc::{self, Channels, ValidChannels, ValidSubbands};
+
+#[cfg(test)]
+mod test;
+
+pub const SBC_LE: u8 = 0;
+pub const SBC_BE: u8 = 1;
+
+/// `struct sbc_struct` of libsbc.
+#[repr(C)]
+pub struct sbc_t {
+    pub flags: c_ulong,
+    pub frequency: u8,
+    pub blocks: u8,
+    pub subbands: u8,
+    pub mode: u8,
+    pub allocation: u8,
+    pub bitpool: u8,
+    pub endian: u8,
+    pub priv_: *mut c_void,
+    pub priv_alloc_base: *mut c_void,
+}
+
+fn read_sample(bytes: &[u8], endian: u8) -> i16 {
+    let bytes = [bytes[0], bytes[1]];
+    match endian {
+        SBC_BE => i16::from_be_bytes(bytes),
+        _ => i16::from_le_bytes(bytes),
+    }
+}
+
+fn write_sample(sample: i16, endian: u8) -> [u8; 2] {
+    match endian {
+        SBC_BE => sample.to_be_bytes(),
+        _ => sample.to_le_bytes(),
+    }
+}
+
+fn decode_as<const CHANNELS: usize, const SUBBANDS: usize>(
+    header: &SBCHeader,
+    filter: &mut FilterState<CHANNELS, SUBBANDS>,
+    mut input: &[u8],
+    endian: u8,
+    output: &mut [u8],
+) -> Result<usize, FrameDecodeError>
+where
+    Channels<CHANNELS>: ValidChannels,
+    sbc::Subbands<SUBBANDS>: ValidSubbands,
+{
+    let mut blocks = [[[0; SUBBANDS]; CHANNELS]; MAX_BLOCKS];
+    let n = FrameDecoder::new(header, filter, &mut input)?.decode_frame(&mut blocks)?;
+    // Whole samples only, as many as fit.
+    let samples = (n * SUBBANDS).min(output.len() / (2 * CHANNELS));
+    for (i, o) in output
+        .chunks_exact_mut(2 * CHANNELS)
+        .take(samples)
+        .enumerate()
+    {
+        for ch in 0..CHANNELS {
+            let sample = blocks[i / SUBBANDS][ch][i % SUBBANDS];
+            o[2 * ch..2 * ch + 2].copy_from_slice(&write_sample(sample, endian));
+        }
+    }
+    Ok(samples * 2 * CHANNELS)
+}
+
+fn check_as<const CHANNELS: usize, const SUBBANDS: usize>(
+    header: &SBCHeader,
+    mut input: &[u8],
+) -> Result<(), FrameDecodeError>
+where
+    Channels<CHANNELS>: ValidChannels,
+    sbc::Subbands<SUBBANDS>: ValidSubbands,
+{
+    FrameReader::<CHANNELS, SUBBANDS, _>::new(header, &mut input).map(|_| ())
+}
+
+fn encode_as<const CHANNELS: usize, const SUBBANDS: usize>(
+    encoder: &mut Encoder<CHANNELS, SUBBANDS>,
+    input: &[u8],
+    endian: u8,
+    mut output: &mut [u8],
+) -> Result<usize, FrameDecodeError>
+where
+    Channels<CHANNELS>: ValidChannels,
+    sbc::Subbands<SUBBANDS>: ValidSubbands,
+{
+    let mut pcm = [[0; CHANNELS]; MAX_BLOCKS * 8];
+    let pcm = &mut pcm[..encoder.frame_samples()];
+    for (sample, bytes) in pcm.iter_mut().zip(input.chunks_exact(2 * CHANNELS)) {
+        for ch in 0..CHANNELS {
+            sample[ch] = read_sample(&bytes[2 * ch..], endian);
+        }
+    }
+    encoder.encode(pcm, &mut output)
+}
+
+/// Synthesis filters for every layout, so that each frame decodes with its own header.
+struct Decoders {
+    mono4: FilterState<1, 4>,
+    mono8: FilterState<1, 8>,
+    stereo4: FilterState<2, 4>,
+    stereo8: FilterState<2, 8>,
+}
+
+impl Decoders {
+    fn check(header: &SBCHeader, input: &[u8]) -> Result<(), FrameDecodeError> {
+        match (header.channel_mode().channels(), header.subbands()) {
+            (1, Subbands::SBC_SB_4) => check_as::<1, 4>(header, input),
+            (1, Subbands::SBC_SB_8) => check_as::<1, 8>(header, input),
+            (_, Subbands::SBC_SB_4) => check_as::<2, 4>(header, input),
+            (_, Subbands::SBC_SB_8) => check_as::<2, 8>(header, input),
+        }
+    }
+
+    fn decode(
+        &mut self,
+        header: &SBCHeader,
+        input: &[u8],
+        endian: u8,
+        output: &mut [u8],
+    ) -> Result<usize, FrameDecodeError> {
+        match (header.channel_mode().channels(), header.subbands()) {
+            (1, Subbands::SBC_SB_4) => decode_as(header, &mut self.mono4, input, endian, output),
+            (1, Subbands::SBC_SB_8) => decode_as(header, &mut self.mono8, input, endian, output),
+            (_, Subbands::SBC_SB_4) => decode_as(header, &mut self.stereo4, input, endian, output),
+            (_, Subbands::SBC_SB_8) => decode_as(header, &mut self.stereo8, input, endian, output),
+        }
+    }
+}
+
+enum Encoders {
+    Mono4(Encoder<1, 4>),
+    Mono8(Encoder<1, 8>),
+    Stereo4(Encoder<2, 4>),
+    Stereo8(Encoder<2, 8>),
+}
+
+impl Encoders {
+    fn new(header: &SBCHeader) -> Result<Self, FrameDecodeError> {
+        let encoder = match (header.channel_mode().channels(), header.subbands()) {
+            (1, Subbands::SBC_SB_4) => Self::Mono4(Encoder::new(header)?),
+            (1, Subbands::SBC_SB_8) => Self::Mono8(Encoder::new(header)?),
+            (_, Subbands::SBC_SB_4) => Self::Stereo4(Encoder::new(header)?),
+            (_, Subbands::SBC_SB_8) => Self::Stereo8(Encoder::new(header)?),
+        };
+        Ok(encoder)
+    }
+
+    fn header(&self) -> &SBCHeader {
+        match self {
+            Self::Mono4(encoder) => encoder.header(),
+            Self::Mono8(encoder) => encoder.header(),
+            Self::Stereo4(encoder) => encoder.header(),
+            Self::Stereo8(encoder) => encoder.header(),
+        }
+    }
+
+    fn set_bitpool(&mut self, bitpool: u8) -> Result<(), FrameDecodeError> {
+        match self {
+            Self::Mono4(encoder) => encoder.set_bitpool(bitpool),
+            Self::Mono8(encoder) => encoder.set_bitpool(bitpool),
+            Self::Stereo4(encoder) => encoder.set_bitpool(bitpool),
+            Self::Stereo8(encoder) => encoder.set_bitpool(bitpool),
+        }
+    }
+
+    fn encode(
+        &mut self,
+        input: &[u8],
+        endian: u8,
+        output: &mut [u8],
+    ) -> Result<usize, FrameDecodeError> {
+        match self {
+            Self::Mono4(encoder) => encode_as(encoder, input, endian, output),
+            Self::Mono8(encoder) => encode_as(encoder, input, endian, output),
+            Self::Stereo4(encoder) => encode_as(encoder, input, endian, output),
+            Self::Stereo8(encoder) => encode_as(encoder, input, endian, output),
+        }
+    }
+}
+
+/// What `priv` of `sbc_t` points to.
+struct Priv {
+    msbc: bool,
+    /// The configuration of the last frame parsed or encoded, once there is one.
+    frame: Option<SBCHeader>,
+    decoders: Decoders,
+    encoder: Option<Encoders>,
+}
+
+impl Priv {
+    fn new(msbc: bool) -> Self {
+        Self {
+            msbc,
+            frame: None,
+            decoders: Decoders {
+                mono4: FilterState::new(),
+                mono8: FilterState::new(),
+                stereo4: FilterState::new(),
+                stereo8: FilterState::new(),
+            },
+            encoder: None,
+        }
+    }
+}
+
+unsafe fn state<'a>(sbc: *mut sbc_t) -> Option<(&'a mut sbc_t, &'a mut Priv)> {
+    let sbc = sbc.as_mut()?;
+    let state = (sbc.priv_ as *mut Priv).as_mut()?;
+    Some((sbc, state))
+}
+
+fn set_defaults(sbc: &mut sbc_t, flags: c_ulong) {
+    sbc.flags = flags;
+    sbc.frequency = Frequency::SBC_FREQ_44100 as u8;
+    sbc.mode = ChannelMode::SBC_MODE_STEREO as u8;
+    sbc.subbands = Subbands::SBC_SB_8 as u8;
+    sbc.blocks = Blocks::SBC_BLK_16 as u8;
+    sbc.bitpool = 32;
+    sbc.endian = if cfg!(target_endian = "big") {
+        SBC_BE
+    } else {
+        SBC_LE
+    };
+}
+
+/// Sets the fields to the configuration of `header`. mSBC has `blocks` set to 15 rather than
+/// to a block code.
+fn set_fields(sbc: &mut sbc_t, header: &SBCHeader) {
+    sbc.frequency = header.frequency() as u8;
+    sbc.mode = header.channel_mode() as u8;
+    sbc.subbands = header.subbands() as u8;
+    sbc.blocks = header
+        .blocks()
+        .map_or(MSBC_BLOCKS as u8, |blocks| blocks as u8);
+    sbc.allocation = header.allocation_method() as u8;
+    sbc.bitpool = header.bitpool();
+}
+
+/// The configuration the fields describe.
+fn fields(sbc: &sbc_t, msbc: bool) -> SBCHeader {
+    if msbc {
+        return SBCHeader::MSBC;
+    }
+    SBCHeader::SBC {
+        frequency: Frequency::decode(sbc.frequency << 6),
+        blocks: Blocks::decode(sbc.blocks << 4),
+        channel_mode: ChannelMode::decode(sbc.mode << 2),
+        allocation_method: AllocationMethod::decode(sbc.allocation << 1),
+        subbands: Subbands::decode(sbc.subbands),
+        bitpool: sbc.bitpool,
+    }
+}
+
+/// Checks the frame at the start of `input` and returns its header, or the error codes of
+/// libsbc: -1 for too little input, -2 for a bad syncword, -3 for a bad CRC and -4 for a
+/// bitpool out of range.
+fn unpack(msbc: bool, input: &[u8]) -> Result<SBCHeader, ssize_t> {
+    if input.len() < 4 {
+        return Err(-1);
+    }
+    let syncword = if msbc { MSBC_SYNCWORD } else { SBC_SYNCWORD };
+    if input[0] != syncword || msbc && (input[1] != 0 || input[2] != 0) {
+        return Err(-2);
+    }
+    let header = SBCHeader::decode_array(&[input[0], input[1], input[2]]).ok_or(-2_isize)?;
+    if header.bitpool() > header.max_bitpool() {
+        return Err(-4);
+    }
+    if input.len() < header.frame_length() {
+        return Err(-1);
+    }
+    match Decoders::check(&header, &input[3..]) {
+        Ok(()) => Ok(header),
+        Err(FrameDecodeError::CRCBed) => Err(-3),
+        Err(_) => Err(-1),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sbc_init(sbc: *mut sbc_t, flags: c_ulong) -> c_int {
+    if sbc.is_null() {
+        return -EIO;
+    }
+    let state = Box::into_raw(Box::new(Priv::new(false))) as *mut c_void;
+    let mut fields = sbc_t {
+        flags,
+        frequency: 0,
+        blocks: 0,
+        subbands: 0,
+        mode: 0,
+        allocation: 0,
+        bitpool: 0,
+        endian: 0,
+        priv_: state,
+        priv_alloc_base: state,
+    };
+    set_defaults(&mut fields, flags);
+    sbc.write(fields);
+    0
+}
+
+/// Starts over with the default configuration, as [`sbc_init`] leaves it.
+#[no_mangle]
+pub unsafe extern "C" fn sbc_reinit(sbc: *mut sbc_t, flags: c_ulong) -> c_int {
+    let Some((sbc, state)) = state(sbc) else {
+        return -EIO;
+    };
+    *state = Priv::new(false);
+    set_defaults(sbc, flags);
+    0
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sbc_init_msbc(sbc: *mut sbc_t, flags: c_ulong) -> c_int {
+    let err = sbc_init(sbc, flags);
+    if err < 0 {
+        return err;
+    }
+    sbc_reinit_msbc(sbc, flags)
+}
+
+/// Starts over in mSBC mode: frames have the mSBC syncword and header, and the fields hold
+/// the fixed mSBC configuration.
+#[no_mangle]
+pub unsafe extern "C" fn sbc_reinit_msbc(sbc: *mut sbc_t, flags: c_ulong) -> c_int {
+    let Some((sbc, state)) = state(sbc) else {
+        return -EIO;
+    };
+    *state = Priv::new(true);
+    set_defaults(sbc, flags);
+    set_fields(sbc, &SBCHeader::MSBC);
+    0
+}
+
+/// Checks the frame at the start of `input` without decoding it, and returns its length.
+#[no_mangle]
+pub unsafe extern "C" fn sbc_parse(
+    sbc: *mut sbc_t,
+    input: *const c_void,
+    input_len: size_t,
+) -> ssize_t {
+    sbc_decode(
+        sbc,
+        input,
+        input_len,
+        core::ptr::null_mut(),
+        0,
+        core::ptr::null_mut(),
+    )
+}
+
+/// Decodes the frame at the start of `input` to interleaved 16 bit PCM in the byte order of
+/// `endian`, as many samples as fit into `output`, and returns the length of the frame.
+#[no_mangle]
+pub unsafe extern "C" fn sbc_decode(
+    sbc: *mut sbc_t,
+    input: *const c_void,
+    input_len: size_t,
+    output: *mut c_void,
+    output_len: size_t,
+    written: *mut size_t,
+) -> ssize_t {
+    let Some((sbc, state)) = state(sbc) else {
+        return -EIO as ssize_t;
+    };
+    if input.is_null() {
+        return -EIO as ssize_t;
+    }
+    let input = slice::from_raw_parts(input as *const u8, input_len);
+    if !output.is_null() && !written.is_null() {
+        *written = 0;
+    }
+    let header = match unpack(state.msbc, input) {
+        Ok(header) => header,
+        Err(err) => return err,
+    };
+    if state.frame.is_none() {
+        set_fields(sbc, &header);
+    } else {
+        sbc.bitpool = header.bitpool();
+    }
+    state.frame = Some(header);
+    let length = header.frame_length() as ssize_t;
+    if output.is_null() {
+        return length;
+    }
+
+    let output = slice::from_raw_parts_mut(output as *mut u8, output_len);
+    match state
+        .decoders
+        .decode(&header, &input[3..], sbc.endian, output)
+    {
+        Ok(n) => {
+            if !written.is_null() {
+                *written = n;
+            }
+            length
+        }
+        Err(_) => -1,
+    }
+}
+
+/// Encodes one frame of interleaved 16 bit PCM in the byte order of `endian` and returns the
+/// number of bytes of `input` it took, or 0 if `input` holds less than a frame.
+#[no_mangle]
+pub unsafe extern "C" fn sbc_encode(
+    sbc: *mut sbc_t,
+    input: *const c_void,
+    input_len: size_t,
+    output: *mut c_void,
+    output_len: size_t,
+    written: *mut ssize_t,
+) -> ssize_t {
+    let Some((sbc, state)) = state(sbc) else {
+        return -EIO as ssize_t;
+    };
+    if input.is_null() {
+        return -EIO as ssize_t;
+    }
+    if !written.is_null() {
+        *written = 0;
+    }
+    let encoder = match &mut state.encoder {
+        Some(encoder) => encoder,
+        encoder => match Encoders::new(&fields(sbc, state.msbc)) {
+            Ok(new) => encoder.insert(new),
+            Err(_) => return -EINVAL as ssize_t,
+        },
+    };
+    if encoder.header().bitpool() != sbc.bitpool && encoder.set_bitpool(sbc.bitpool).is_err() {
+        return -EINVAL as ssize_t;
+    }
+    let header = *encoder.header();
+    state.frame = Some(header);
+
+    let codesize = header.frame_samples() * header.channel_mode().channels() * 2;
+    if input_len < codesize {
+        return 0;
+    }
+    if output.is_null() || output_len < header.frame_length() {
+        return -ENOSPC as ssize_t;
+    }
+    let input = slice::from_raw_parts(input as *const u8, codesize);
+    let output = slice::from_raw_parts_mut(output as *mut u8, output_len);
+    match encoder.encode(input, sbc.endian, output) {
+        Ok(n) => {
+            if !written.is_null() {
+                *written = n as ssize_t;
+            }
+            codesize as ssize_t
+        }
+        Err(_) => -EIO as ssize_t,
+    }
+}
+
+/// The configuration in use: that of the last frame, or of the fields before the first one.
+unsafe fn config(sbc: *mut sbc_t) -> Option<SBCHeader> {
+    let (sbc, state) = state(sbc)?;
+    match state.frame {
+        Some(frame) if frame.bitpool() == sbc.bitpool => Some(frame),
+        _ => Some(fields(sbc, state.msbc)),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sbc_get_frame_length(sbc: *mut sbc_t) -> size_t {
+    config(sbc).map_or(0, |header| header.frame_length())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sbc_get_frame_duration(sbc: *mut sbc_t) -> c_uint {
+    config(sbc).map_or(0, |header| {
+        let frequency = header.frequency().frequency() as usize;
+        (1_000_000 * header.frame_samples() / frequency) as c_uint
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sbc_get_codesize(sbc: *mut sbc_t) -> size_t {
+    config(sbc).map_or(0, |header| {
+        header.frame_samples() * header.channel_mode().channels() * 2
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sbc_get_implementation_info(_sbc: *mut sbc_t) -> *const c_char {
+    c"mini_sbc".as_ptr()
+}
+
+/// Frees what [`sbc_init`] allocated and clears `sbc`.
+#[no_mangle]
+pub unsafe extern "C" fn sbc_finish(sbc: *mut sbc_t) {
+    let Some(sbc) = sbc.as_mut() else {
+        return;
+    };
+    if !sbc.priv_alloc_base.is_null() {
+        drop(Box::from_raw(sbc.priv_alloc_base as *mut Priv));
+    }
+    core::ptr::write_bytes(sbc, 0, 1);
+}
@@ -0,0 +1,313 @@
+use core::ffi::{c_void, CStr};
+use core::mem::MaybeUninit;
+use core::ptr::{null, null_mut};
+
+use crate::*;
+
+fn init() -> sbc_t {
+    let mut sbc = MaybeUninit::uninit();
+    assert_eq!(unsafe { sbc_init(sbc.as_mut_ptr(), 0) }, 0);
+    unsafe { sbc.assume_init() }
+}
+
+fn init_msbc() -> sbc_t {
+    let mut sbc = MaybeUninit::uninit();
+    assert_eq!(unsafe { sbc_init_msbc(sbc.as_mut_ptr(), 0) }, 0);
+    unsafe { sbc.assume_init() }
+}
+
+fn tone(samples: usize, channels: usize, rate: f64) -> Vec<i16> {
+    (0..samples * channels)
+        .map(|i| {
+            let t = (i / channels) as f64 / rate;
+            let hz = 440.0 * (1 + i % channels) as f64;
+            (8000.0 * (2.0 * core::f64::consts::PI * hz * t).sin()) as i16
+        })
+        .collect()
+}
+
+/// Encodes interleaved `pcm` frame by frame.
+fn encode(sbc: &mut sbc_t, pcm: &[i16]) -> Vec<u8> {
+    let bytes: Vec<u8> = pcm.iter().flat_map(|s| s.to_le_bytes()).collect();
+    let mut input = &bytes[..];
+    let mut stream = Vec::new();
+    loop {
+        let mut frame = [0; 512];
+        let mut written = 0;
+        let n = unsafe {
+            sbc_encode(
+                sbc,
+                input.as_ptr() as *const c_void,
+                input.len(),
+                frame.as_mut_ptr() as *mut c_void,
+                frame.len(),
+                &mut written,
+            )
+        };
+        assert!(n >= 0);
+        if n == 0 {
+            return stream;
+        }
+        assert_eq!(n as usize, unsafe { sbc_get_codesize(sbc) });
+        assert_eq!(written as usize, unsafe { sbc_get_frame_length(sbc) });
+        stream.extend_from_slice(&frame[..written as usize]);
+        input = &input[n as usize..];
+    }
+}
+
+/// Decodes a stream frame by frame to PCM bytes.
+fn decode(sbc: &mut sbc_t, mut stream: &[u8]) -> Vec<u8> {
+    let mut pcm = Vec::new();
+    while !stream.is_empty() {
+        let mut frame = [0; 1024];
+        let mut written = 0;
+        let n = unsafe {
+            sbc_decode(
+                sbc,
+                stream.as_ptr() as *const c_void,
+                stream.len(),
+                frame.as_mut_ptr() as *mut c_void,
+                frame.len(),
+                &mut written,
+            )
+        };
+        assert!(n > 0, "{n}");
+        assert_eq!(written, unsafe { sbc_get_codesize(sbc) });
+        pcm.extend_from_slice(&frame[..written]);
+        stream = &stream[n as usize..];
+    }
+    pcm
+}
+
+fn parse(sbc: &mut sbc_t, stream: &[u8]) -> isize {
+    unsafe { sbc_parse(sbc, stream.as_ptr() as *const c_void, stream.len()) }
+}
+
+#[test]
+fn test_init() {
+    let mut sbc = init();
+    assert_eq!(sbc.frequency, 2);
+    assert_eq!(sbc.blocks, 3);
+    assert_eq!(sbc.subbands, 1);
+    assert_eq!(sbc.mode, 2);
+    assert_eq!(sbc.allocation, 0);
+    assert_eq!(sbc.bitpool, 32);
+    assert_eq!(sbc.endian, SBC_LE);
+    assert!(!sbc.priv_.is_null());
+    unsafe {
+        assert_eq!(sbc_get_frame_length(&mut sbc), 76);
+        assert_eq!(sbc_get_codesize(&mut sbc), 512);
+        assert_eq!(sbc_get_frame_duration(&mut sbc), 2902);
+        assert!(!CStr::from_ptr(sbc_get_implementation_info(&mut sbc)).is_empty());
+    }
+    sbc.mode = 0;
+    sbc.subbands = 0;
+    sbc.blocks = 1;
+    unsafe {
+        assert_eq!(sbc_get_frame_length(&mut sbc), 4 + 2 + 32);
+        assert_eq!(sbc_get_codesize(&mut sbc), 64);
+        assert_eq!(sbc_reinit(&mut sbc, 7), 0);
+    }
+    assert_eq!(
+        (sbc.flags, sbc.mode, sbc.subbands, sbc.blocks),
+        (7, 2, 1, 3)
+    );
+    unsafe {
+        sbc_finish(&mut sbc);
+        assert!(sbc.priv_.is_null());
+        assert_eq!(sbc_reinit(&mut sbc, 0), -EIO);
+        assert_eq!(sbc_init(null_mut(), 0), -EIO);
+        assert_eq!(sbc_get_frame_length(null_mut()), 0);
+        sbc_finish(null_mut());
+    }
+}
+
+#[test]
+fn test_round_trip() {
+    let mut encoder = init();
+    encoder.mode = 3;
+    encoder.bitpool = 53;
+    let pcm = tone(128 * 20, 2, 44100.0);
+    let stream = encode(&mut encoder, &pcm);
+    assert_eq!(stream.len(), 20 * 119);
+
+    let mut decoder = init();
+    decoder.mode = 0;
+    decoder.bitpool = 0;
+    assert_eq!(parse(&mut decoder, &stream), 119);
+    assert_eq!((decoder.mode, decoder.bitpool), (3, 53));
+    let decoded = decode(&mut decoder, &stream);
+    assert_eq!(decoded.len(), pcm.len() * 2);
+    let delay = 2 * (9 * 8 + 1);
+    let (mut signal, mut noise) = (0.0, 0.0);
+    for (i, s) in pcm[..pcm.len() - delay].iter().enumerate() {
+        let d = i16::from_le_bytes([decoded[2 * (i + delay)], decoded[2 * (i + delay) + 1]]);
+        signal += (*s as f64).powi(2);
+        noise += (*s as f64 - d as f64).powi(2);
+    }
+    assert!(10.0 * (signal / noise).log10() > 40.0);
+
+    // The bitpool can change between frames.
+    encoder.bitpool = 20;
+    let low = encode(&mut encoder, &pcm[..256]);
+    assert_eq!(low.len(), unsafe { sbc_get_frame_length(&mut encoder) });
+    assert_eq!(low.len(), 4 + 8 + (8 + 16 * 20) / 8);
+    assert_eq!(parse(&mut decoder, &low), low.len() as isize);
+    assert_eq!(decoder.bitpool, 20);
+
+    let mut big = init();
+    big.endian = SBC_BE;
+    let swapped: Vec<u8> = decode(&mut big, &stream)
+        .chunks_exact(2)
+        .flat_map(|s| [s[1], s[0]])
+        .collect();
+    assert_eq!(swapped, decoded);
+
+    let golden = include_bytes!("../../testdata/stereo_b16_sb8.sbc");
+    let expected = include_bytes!("../../testdata/stereo_b16_sb8.pcm");
+    let decoded = decode(&mut init(), golden);
+    for (a, b) in decoded.chunks_exact(2).zip(expected.chunks_exact(2)) {
+        let (a, b) = (
+            i16::from_le_bytes([a[0], a[1]]),
+            i16::from_le_bytes([b[0], b[1]]),
+        );
+        assert!((a as i32 - b as i32).abs() <= 4);
+    }
+    for mut sbc in [encoder, decoder, big] {
+        unsafe { sbc_finish(&mut sbc) };
+    }
+}
+
+#[test]
+fn test_errors() {
+    let mut sbc = init();
+    let mut frame = encode(&mut sbc, &tone(128, 2, 44100.0));
+    unsafe { sbc_finish(&mut sbc) };
+    let mut sbc = init();
+
+    assert_eq!(parse(&mut sbc, &frame[..3]), -1);
+    assert_eq!(parse(&mut sbc, &frame[..frame.len() - 1]), -1);
+    frame[3] ^= 1;
+    assert_eq!(parse(&mut sbc, &frame), -3);
+    frame[3] ^= 1;
+    frame[0] = 0xAD;
+    assert_eq!(parse(&mut sbc, &frame), -2);
+    frame[0] = 0x9C;
+    let bitpool = frame[2];
+    frame[2] = 255;
+    frame[1] &= !0x0C;
+    assert_eq!(parse(&mut sbc, &frame), -4);
+    frame[1] |= 0x08;
+    frame[2] = bitpool;
+    unsafe {
+        assert_eq!(
+            sbc_parse(null_mut(), frame.as_ptr() as *const c_void, 4),
+            -EIO as isize
+        );
+        assert_eq!(sbc_parse(&mut sbc, null(), 4), -EIO as isize);
+    }
+
+    // 99 bytes of output take 24 whole stereo samples.
+    let mut pcm = [0_u8; 100];
+    let mut written = 0;
+    let n = unsafe {
+        sbc_decode(
+            &mut sbc,
+            frame.as_ptr() as *const c_void,
+            frame.len(),
+            pcm.as_mut_ptr() as *mut c_void,
+            99,
+            &mut written,
+        )
+    };
+    assert_eq!((n, written), (frame.len() as isize, 96));
+
+    let input = [0_u8; 512];
+    let mut output = [0_u8; 76];
+    let mut written = 0;
+    let encode = |sbc: &mut sbc_t, input: &[u8], output: *mut c_void, len, written| unsafe {
+        sbc_encode(
+            sbc,
+            input.as_ptr() as *const c_void,
+            input.len(),
+            output,
+            len,
+            written,
+        )
+    };
+    let out = output.as_mut_ptr() as *mut c_void;
+    assert_eq!(encode(&mut sbc, &input[..511], out, 76, &mut written), 0);
+    assert_eq!(
+        encode(&mut sbc, &input, out, 75, &mut written),
+        -ENOSPC as isize
+    );
+    assert_eq!(
+        encode(&mut sbc, &input, null_mut(), 76, &mut written),
+        -ENOSPC as isize
+    );
+    assert_eq!(encode(&mut sbc, &input, out, 76, &mut written), 512);
+    assert_eq!(written, 76);
+    // The frame length follows the bitpool.
+    sbc.bitpool = 255;
+    assert_eq!(
+        encode(&mut sbc, &input, out, 76, &mut written),
+        -ENOSPC as isize
+    );
+    sbc.bitpool = 0;
+    assert_eq!(encode(&mut sbc, &input, out, 76, null_mut()), 512);
+    unsafe { sbc_finish(&mut sbc) };
+
+    let mut sbc = init();
+    sbc.mode = 0;
+    sbc.bitpool = 200;
+    assert_eq!(
+        encode(&mut sbc, &input, out, 76, &mut written),
+        -EINVAL as isize
+    );
+    unsafe { sbc_finish(&mut sbc) };
+}
+
+#[test]
+fn test_msbc() {
+    let mut sbc = init_msbc();
+    assert_eq!(
+        (
+            sbc.frequency,
+            sbc.blocks,
+            sbc.subbands,
+            sbc.mode,
+            sbc.bitpool
+        ),
+        (0, 15, 1, 0, 26)
+    );
+    unsafe {
+        assert_eq!(sbc_get_frame_length(&mut sbc), 57);
+        assert_eq!(sbc_get_codesize(&mut sbc), 240);
+        assert_eq!(sbc_get_frame_duration(&mut sbc), 7500);
+    }
+    let pcm = tone(120 * 8, 1, 16000.0);
+    let stream = encode(&mut sbc, &pcm);
+    assert_eq!(stream.len(), 8 * 57);
+    assert_eq!(&stream[..3], &[0xAD, 0, 0]);
+    unsafe { sbc_finish(&mut sbc) };
+
+    let mut decoder = init_msbc();
+    assert_eq!(decode(&mut decoder, &stream).len(), pcm.len() * 2);
+    let mut sbc = init();
+    let frame = encode(&mut sbc, &tone(128, 2, 44100.0));
+    assert_eq!(parse(&mut decoder, &frame), -2);
+    assert_eq!(parse(&mut sbc, &stream), -2);
+    let mut bad = stream[..57].to_vec();
+    bad[2] = 1;
+    assert_eq!(parse(&mut decoder, &bad), -2);
+
+    unsafe {
+        assert_eq!(sbc_reinit_msbc(&mut sbc, 0), 0);
+        assert_eq!(parse(&mut sbc, &stream), 57);
+        assert_eq!(sbc_reinit(&mut sbc, 0), 0);
+        assert_eq!(parse(&mut sbc, &frame), frame.len() as isize);
+        sbc_finish(&mut sbc);
+        sbc_finish(&mut decoder);
+        assert_eq!(sbc_reinit_msbc(&mut sbc, 0), -EIO);
+    }
+}
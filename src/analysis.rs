@@ -0,0 +1,84 @@
+//! The analysis filter bank of the encoder, splitting PCM into subband samples.
+
+use crate::frame_decoder::SBCDEC_FIXED_EXTRA_BITS;
+use crate::sbc::{Channels, Subbands, ValidChannels, ValidSubbands, FILTER_ORDER};
+use crate::table::{A_COS_4, A_COS_8, A_PROTO_4, A_PROTO_8};
+
+fn cos<const SUBBANDS: usize>(k: usize, i: usize) -> i64 {
+    if SUBBANDS == 4 {
+        A_COS_4[k][i] as i64
+    } else {
+        A_COS_8[k][i] as i64
+    }
+}
+
+/// Splits blocks of PCM into subband samples in the units of
+/// [`FrameReader::read_block`](crate::frame_decoder::FrameReader::read_block), so that
+/// a [`FilterState`](crate::filter_state::FilterState) reconstructs the input delayed by
+/// [`codec_delay`](crate::sbc::codec_delay).
+#[derive(Debug, Copy, Clone)]
+pub struct AnalysisFilter<const CHANNELS: usize, const SUBBANDS: usize>
+where
+    Channels<CHANNELS>: ValidChannels,
+    Subbands<SUBBANDS>: ValidSubbands,
+{
+    /// The last `FILTER_ORDER` blocks of input, newest sample first.
+    history: [[i32; FILTER_ORDER * 8]; CHANNELS],
+}
+
+impl<const CHANNELS: usize, const SUBBANDS: usize> Default for AnalysisFilter<CHANNELS, SUBBANDS>
+where
+    Channels<CHANNELS>: ValidChannels,
+    Subbands<SUBBANDS>: ValidSubbands,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const CHANNELS: usize, const SUBBANDS: usize> AnalysisFilter<CHANNELS, SUBBANDS>
+where
+    Channels<CHANNELS>: ValidChannels,
+    Subbands<SUBBANDS>: ValidSubbands,
+{
+    pub const fn new() -> Self {
+        Self {
+            history: [[0; FILTER_ORDER * 8]; CHANNELS],
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    pub fn filter(&mut self, pcm: &[[i16; SUBBANDS]; CHANNELS]) -> [[i32; SUBBANDS]; CHANNELS] {
+        let m = SUBBANDS;
+        let proto: &[i32] = if m == 4 { &A_PROTO_4 } else { &A_PROTO_8 };
+        let mut s = [[0; SUBBANDS]; CHANNELS];
+        for ch in 0..CHANNELS {
+            let x = &mut self.history[ch][..FILTER_ORDER * m];
+            x.copy_within(..(FILTER_ORDER - 1) * m, m);
+            for i in 0..m {
+                x[m - 1 - i] = pcm[ch][i] as i32;
+            }
+            // Windowed and folded input in Q15.
+            let mut y = [0_i64; 16];
+            for (i, y) in y[..2 * m].iter_mut().enumerate() {
+                let mut acc = 0_i64;
+                for j in 0..FILTER_ORDER / 2 {
+                    acc += x[i + 2 * m * j] as i64 * proto[i + 2 * m * j] as i64;
+                }
+                *y = (acc + (1 << 15)) >> 16;
+            }
+            for (k, s) in s[ch].iter_mut().enumerate() {
+                let mut acc = 0_i64;
+                for (i, y) in y[..2 * m].iter().enumerate() {
+                    acc += cos::<SUBBANDS>(k, i) * y;
+                }
+                let shift = 15 + 28 - SBCDEC_FIXED_EXTRA_BITS as u32;
+                *s = ((acc + (1 << (shift - 1))) >> shift) as i32;
+            }
+        }
+        s
+    }
+}
@@ -0,0 +1,324 @@
+//! [`Stream`] and [`Sink`] adapters over `futures` I/O, enabled by the `futures` feature.
+//!
+//! Both work on one frame at a time in a fixed buffer: the decoder only reads when it is
+//! polled, and the encoder accepts a new frame only once the previous one is written out.
+
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use std::io;
+
+use futures_core::Stream;
+use futures_io::{AsyncRead, AsyncWrite};
+use futures_sink::Sink;
+
+use crate::encoder::Encoder;
+use crate::filter_state::FilterState;
+use crate::frame_decoder::{FrameDecodeError, FrameDecoder};
+use crate::header::{SBCHeader, MAX_BLOCKS, MAX_FRAME_LENGTH, MSBC_SYNCWORD, SBC_SYNCWORD};
+use crate::sbc::{Channels, Subbands, ValidChannels, ValidSubbands};
+
+/// Samples per channel of the longest frame.
+pub const MAX_FRAME_SAMPLES: usize = MAX_BLOCKS * 8;
+
+#[derive(Debug)]
+pub enum StreamError {
+    Io(io::Error),
+    Frame(FrameDecodeError),
+    /// Bytes that do not start a valid header were skipped up to the next syncword.
+    Sync,
+}
+
+impl From<io::Error> for StreamError {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+impl From<FrameDecodeError> for StreamError {
+    fn from(value: FrameDecodeError) -> Self {
+        Self::Frame(value)
+    }
+}
+
+/// Interleaved PCM of one frame.
+#[derive(Debug, Copy, Clone)]
+pub struct PcmFrame<const CHANNELS: usize> {
+    samples: [[i16; CHANNELS]; MAX_FRAME_SAMPLES],
+    len: usize,
+}
+
+impl<const CHANNELS: usize> PcmFrame<CHANNELS> {
+    /// `None` if `samples` is longer than any frame.
+    pub fn from_samples(samples: &[[i16; CHANNELS]]) -> Option<Self> {
+        if samples.len() > MAX_FRAME_SAMPLES {
+            return None;
+        }
+        let mut frame = Self {
+            samples: [[0; CHANNELS]; MAX_FRAME_SAMPLES],
+            len: samples.len(),
+        };
+        frame.samples[..samples.len()].copy_from_slice(samples);
+        Some(frame)
+    }
+
+    pub fn samples(&self) -> &[[i16; CHANNELS]] {
+        &self.samples[..self.len]
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+/// Decodes the SBC stream of an [`AsyncRead`] to one [`PcmFrame`] per frame.
+///
+/// Errors do not end the stream: a frame failing its CRC is dropped, and garbage is skipped
+/// up to the next syncword, each reported once. Ends at the end of the input, with
+/// [`io::ErrorKind::UnexpectedEof`] first if it stops inside a frame.
+#[derive(Debug)]
+pub struct DecodeStream<R, const CHANNELS: usize, const SUBBANDS: usize>
+where
+    Channels<CHANNELS>: ValidChannels,
+    Subbands<SUBBANDS>: ValidSubbands,
+{
+    reader: R,
+    filter: FilterState<CHANNELS, SUBBANDS>,
+    header: Option<SBCHeader>,
+    buffer: [u8; MAX_FRAME_LENGTH],
+    filled: usize,
+}
+
+impl<R: AsyncRead + Unpin, const CHANNELS: usize, const SUBBANDS: usize>
+    DecodeStream<R, CHANNELS, SUBBANDS>
+where
+    Channels<CHANNELS>: ValidChannels,
+    Subbands<SUBBANDS>: ValidSubbands,
+{
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            filter: FilterState::new(),
+            header: None,
+            buffer: [0; MAX_FRAME_LENGTH],
+            filled: 0,
+        }
+    }
+
+    /// Header of the last frame decoded.
+    pub fn header(&self) -> Option<&SBCHeader> {
+        self.header.as_ref()
+    }
+
+    pub fn filter_state(&self) -> &FilterState<CHANNELS, SUBBANDS> {
+        &self.filter
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    /// Discards a partly read frame.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Drops the first buffered byte and anything after it up to the next syncword.
+    fn resync(&mut self) {
+        let skip = self.buffer[1..self.filled]
+            .iter()
+            .position(|&b| b == SBC_SYNCWORD || b == MSBC_SYNCWORD)
+            .map_or(self.filled, |p| p + 1);
+        self.buffer.copy_within(skip..self.filled, 0);
+        self.filled -= skip;
+    }
+
+    fn decode(&mut self, header: &SBCHeader) -> Result<PcmFrame<CHANNELS>, StreamError> {
+        let mut input = &self.buffer[3..self.filled];
+        let mut blocks = [[[0; SUBBANDS]; CHANNELS]; MAX_BLOCKS];
+        let n =
+            FrameDecoder::new(header, &mut self.filter, &mut input)?.decode_frame(&mut blocks)?;
+        self.header = Some(*header);
+        let mut frame = PcmFrame {
+            samples: [[0; CHANNELS]; MAX_FRAME_SAMPLES],
+            len: n * SUBBANDS,
+        };
+        for (pcm, block) in frame.samples.chunks_exact_mut(SUBBANDS).zip(&blocks[..n]) {
+            for (i, sample) in pcm.iter_mut().enumerate() {
+                for ch in 0..CHANNELS {
+                    sample[ch] = block[ch][i];
+                }
+            }
+        }
+        Ok(frame)
+    }
+}
+
+impl<R: AsyncRead + Unpin, const CHANNELS: usize, const SUBBANDS: usize> Stream
+    for DecodeStream<R, CHANNELS, SUBBANDS>
+where
+    Channels<CHANNELS>: ValidChannels,
+    Subbands<SUBBANDS>: ValidSubbands,
+{
+    type Item = Result<PcmFrame<CHANNELS>, StreamError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            let mut header = None;
+            let mut need = 3;
+            if this.filled >= 3 {
+                match SBCHeader::decode_array(&[this.buffer[0], this.buffer[1], this.buffer[2]]) {
+                    Some(h) if h.bitpool() <= h.max_bitpool() => {
+                        header = Some(h);
+                        need = h.frame_length();
+                    }
+                    _ => {
+                        this.resync();
+                        return Poll::Ready(Some(Err(StreamError::Sync)));
+                    }
+                }
+            }
+            if let Some(header) = header.filter(|_| this.filled == need) {
+                let frame = this.decode(&header);
+                this.filled = 0;
+                return Poll::Ready(Some(frame));
+            }
+            match Pin::new(&mut this.reader).poll_read(cx, &mut this.buffer[this.filled..need]) {
+                Poll::Ready(Ok(0)) => {
+                    if this.filled == 0 {
+                        return Poll::Ready(None);
+                    }
+                    this.filled = 0;
+                    return Poll::Ready(Some(Err(
+                        io::Error::from(io::ErrorKind::UnexpectedEof).into()
+                    )));
+                }
+                Poll::Ready(Ok(n)) => this.filled += n,
+                Poll::Ready(Err(e)) if e.kind() == io::ErrorKind::Interrupted => {}
+                Poll::Ready(Err(e)) => return Poll::Ready(Some(Err(e.into()))),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+/// Encodes each [`PcmFrame`] sent to it to one SBC frame written to an [`AsyncWrite`].
+///
+/// Frames must hold exactly [`Encoder::frame_samples`] samples, others fail with
+/// [`FrameDecodeError::SizeBed`]. [`Sink::poll_ready`] waits until the previous frame is
+/// fully written.
+#[derive(Debug)]
+pub struct EncodeSink<W, const CHANNELS: usize, const SUBBANDS: usize>
+where
+    Channels<CHANNELS>: ValidChannels,
+    Subbands<SUBBANDS>: ValidSubbands,
+{
+    writer: W,
+    encoder: Encoder<CHANNELS, SUBBANDS>,
+    buffer: [u8; MAX_FRAME_LENGTH],
+    written: usize,
+    len: usize,
+}
+
+impl<W: AsyncWrite + Unpin, const CHANNELS: usize, const SUBBANDS: usize>
+    EncodeSink<W, CHANNELS, SUBBANDS>
+where
+    Channels<CHANNELS>: ValidChannels,
+    Subbands<SUBBANDS>: ValidSubbands,
+{
+    pub fn new(writer: W, encoder: Encoder<CHANNELS, SUBBANDS>) -> Self {
+        Self {
+            writer,
+            encoder,
+            buffer: [0; MAX_FRAME_LENGTH],
+            written: 0,
+            len: 0,
+        }
+    }
+
+    pub fn encoder(&self) -> &Encoder<CHANNELS, SUBBANDS> {
+        &self.encoder
+    }
+
+    /// For changing the bitpool between frames.
+    pub fn encoder_mut(&mut self) -> &mut Encoder<CHANNELS, SUBBANDS> {
+        &mut self.encoder
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    /// Discards a frame not fully written yet.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), StreamError>> {
+        while self.written < self.len {
+            match Pin::new(&mut self.writer).poll_write(cx, &self.buffer[self.written..self.len]) {
+                Poll::Ready(Ok(0)) => {
+                    return Poll::Ready(Err(io::Error::from(io::ErrorKind::WriteZero).into()))
+                }
+                Poll::Ready(Ok(n)) => self.written += n,
+                Poll::Ready(Err(e)) if e.kind() == io::ErrorKind::Interrupted => {}
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e.into())),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<W: AsyncWrite + Unpin, const CHANNELS: usize, const SUBBANDS: usize> Sink<PcmFrame<CHANNELS>>
+    for EncodeSink<W, CHANNELS, SUBBANDS>
+where
+    Channels<CHANNELS>: ValidChannels,
+    Subbands<SUBBANDS>: ValidSubbands,
+{
+    type Error = StreamError;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.get_mut().poll_drain(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: PcmFrame<CHANNELS>) -> Result<(), Self::Error> {
+        let this = self.get_mut();
+        if this.written < this.len {
+            // `poll_ready` was not called, keep the pending frame intact.
+            return Err(io::Error::from(io::ErrorKind::WouldBlock).into());
+        }
+        let len = this
+            .encoder
+            .encode(item.samples(), &mut &mut this.buffer[..])?;
+        this.written = 0;
+        this.len = len;
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        match this.poll_drain(cx) {
+            Poll::Ready(Ok(())) => {}
+            other => return other,
+        }
+        Pin::new(&mut this.writer)
+            .poll_flush(cx)
+            .map_err(Into::into)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        match this.poll_drain(cx) {
+            Poll::Ready(Ok(())) => {}
+            other => return other,
+        }
+        Pin::new(&mut this.writer)
+            .poll_close(cx)
+            .map_err(Into::into)
+    }
+}
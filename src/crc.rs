@@ -31,9 +31,10 @@ pub const fn crc8(init: u8, data: &[u8], bits: usize) -> u8 {
         crc = CRC_TABLE[(crc ^ data[i]) as usize];
     });
 
+    let last = bits / 8;
     let bits = bits % 8;
     if bits != 0 {
-        crc ^= data[bits / 8] & (0xFF << (8 - bits));
+        crc ^= data[last] & (0xFF << (8 - bits));
         const_for!(_i in (0, bits) {
             if (crc & 0x80) != 0 {
                 crc = (crc << 1) ^ CRC_POLY;
@@ -42,5 +43,5 @@ pub const fn crc8(init: u8, data: &[u8], bits: usize) -> u8 {
             }
         });
     }
-    return crc;
+    crc
 }
@@ -0,0 +1,174 @@
+use crate::filter_state::{FilterState, SynthesisFilter};
+use crate::frame_decoder::{dequantize_sample, FrameDecodeError, FrameReader};
+use crate::header::{SBCHeader, MAX_BLOCKS};
+use crate::io::ByteInput;
+use crate::sbc::{Channels, Subbands, ValidChannels, ValidSubbands};
+
+/// Which mono signal to take out of a two channel stream.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum ChannelSelect {
+    Left,
+    Right,
+    /// `(L + R) / 2`
+    #[default]
+    Downmix,
+}
+
+/// Dequantizes one block straight into the mono signal picked by `select`.
+///
+/// Synthesis is linear, so mixing the subband samples is the same as mixing the PCM output.
+/// Channels that do not contribute are skipped: for a mid/side coded subband the downmix is
+/// just the mid signal, and only one channel of a left/right coded subband is needed for
+/// [`ChannelSelect::Left`] or [`ChannelSelect::Right`].
+pub fn dequantize_mono<const SUBBANDS: usize>(
+    select: ChannelSelect,
+    joint: u8,
+    scale_factor: &[[u8; SUBBANDS]; 2],
+    bits: &[[u8; SUBBANDS]; 2],
+    quantized: &[[u16; SUBBANDS]; 2],
+) -> [[i32; SUBBANDS]; 1] {
+    let mut sample = [[0_i32; SUBBANDS]; 1];
+    for sb in 0..SUBBANDS {
+        let s =
+            |ch: usize| dequantize_sample(scale_factor[ch][sb], bits[ch][sb], quantized[ch][sb]);
+        sample[0][sb] = if (joint & (1 << sb)) != 0 {
+            match select {
+                ChannelSelect::Left => s(0) + s(1),
+                ChannelSelect::Right => s(0) - s(1),
+                ChannelSelect::Downmix => s(0),
+            }
+        } else {
+            match select {
+                ChannelSelect::Left => s(0),
+                ChannelSelect::Right => s(1),
+                ChannelSelect::Downmix => (s(0) + s(1)) >> 1,
+            }
+        };
+    }
+    sample
+}
+
+/// Decodes a two channel frame through a single channel synthesis filter, see
+/// [`ChannelSelect`].
+#[derive(Debug)]
+pub struct MonoFrameDecoder<
+    'd,
+    'b,
+    const SUBBANDS: usize,
+    B: ByteInput,
+    F: SynthesisFilter<1, SUBBANDS> = FilterState<1, SUBBANDS>,
+> where
+    Subbands<SUBBANDS>: ValidSubbands,
+{
+    decoder: &'d mut F,
+    reader: FrameReader<'b, 2, SUBBANDS, B>,
+    select: ChannelSelect,
+}
+
+impl<'d, 'b, const SUBBANDS: usize, B: ByteInput, F: SynthesisFilter<1, SUBBANDS>>
+    MonoFrameDecoder<'d, 'b, SUBBANDS, B, F>
+where
+    Channels<2>: ValidChannels,
+    Subbands<SUBBANDS>: ValidSubbands,
+{
+    pub fn new_no_crc(
+        header: &SBCHeader,
+        select: ChannelSelect,
+        decoder: &'d mut F,
+        buffer: &'b mut B,
+    ) -> Result<Self, FrameDecodeError> {
+        let reader = FrameReader::new_no_crc(header, buffer)?;
+        Ok(Self {
+            decoder,
+            reader,
+            select,
+        })
+    }
+
+    pub fn new(
+        header: &SBCHeader,
+        select: ChannelSelect,
+        decoder: &'d mut F,
+        buffer: &'b mut B,
+    ) -> Result<Self, FrameDecodeError> {
+        let reader = FrameReader::new(header, buffer)?;
+        Ok(Self {
+            decoder,
+            reader,
+            select,
+        })
+    }
+
+    pub fn new_skip_crc(
+        header: &SBCHeader,
+        select: ChannelSelect,
+        decoder: &'d mut F,
+        buffer: &'b mut B,
+    ) -> Result<Self, FrameDecodeError> {
+        let reader = FrameReader::new_skip_crc(header, buffer)?;
+        Ok(Self {
+            decoder,
+            reader,
+            select,
+        })
+    }
+
+    pub fn reader(&self) -> &FrameReader<'b, 2, SUBBANDS, B> {
+        &self.reader
+    }
+
+    pub fn read_block(&mut self) -> Result<[[i32; SUBBANDS]; 1], FrameDecodeError> {
+        let q = self.reader.read_quantized()?;
+        Ok(dequantize_mono(
+            self.select,
+            self.reader.joint(),
+            self.reader.scale_factor(),
+            self.reader.bits(),
+            &q,
+        ))
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<[[F::Sample; SUBBANDS]; 1], FrameDecodeError> {
+        let sample = self.read_block()?;
+        Ok(self.decoder.filter(&sample))
+    }
+
+    /// Same as [`FrameDecoder::decode_frame`](crate::frame_decoder::FrameDecoder::decode_frame).
+    pub fn decode_frame(
+        &mut self,
+        o: &mut [[[F::Sample; SUBBANDS]; 1]],
+    ) -> Result<usize, FrameDecodeError> {
+        let blocks = self.reader.blocks();
+        if blocks == 0 {
+            return Err(FrameDecodeError::NoBlock);
+        }
+        if o.len() < blocks {
+            return Err(FrameDecodeError::SizeBed);
+        }
+        let mut sample = [[[0_i32; SUBBANDS]; 1]; MAX_BLOCKS];
+        for b in 0..blocks {
+            sample[b] = self.read_block()?;
+        }
+        self.decoder
+            .filter_frame(&sample[..blocks], &mut o[..blocks]);
+        Ok(blocks)
+    }
+}
+
+impl<'d, 'b, const SUBBANDS: usize, B: ByteInput, F: SynthesisFilter<1, SUBBANDS>> Iterator
+    for MonoFrameDecoder<'d, 'b, SUBBANDS, B, F>
+where
+    Channels<2>: ValidChannels,
+    Subbands<SUBBANDS>: ValidSubbands,
+{
+    type Item = [[F::Sample; SUBBANDS]; 1];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next() {
+            Ok(v) => Some(v),
+            Err(FrameDecodeError::NoBlock) => None,
+            Err(e) => panic!("{:?}", e),
+        }
+    }
+}
@@ -0,0 +1,219 @@
+//! Clock drift compensation: a cubic Farrow resampler running at a ratio a few hundred ppm
+//! off unity, steered by the fill level of the buffer between decoder and output.
+
+/// Step of one input sample in the Q32 phase of [`DriftResampler`].
+const ONE: u64 = 1 << 32;
+
+/// Interpolates between `h[1]` and `h[2]` at `mu`, a Q16 fraction, with a Catmull-Rom cubic
+/// in Farrow form.
+pub(crate) fn interpolate(h: &[i16; 4], mu: i64) -> i16 {
+    let [h0, h1, h2, h3] = h.map(|v| v as i64);
+    // Twice the polynomial coefficients, to stay in integers.
+    let c1 = h2 - h0;
+    let c2 = 2 * h0 - 5 * h1 + 4 * h2 - h3;
+    let c3 = h3 - h0 + 3 * (h1 - h2);
+    let mut acc = c3;
+    acc = ((acc * mu) >> 16) + c2;
+    acc = ((acc * mu) >> 16) + c1;
+    acc = (acc * mu) >> 16;
+    (h1 + ((acc + 1) >> 1)).clamp(i16::MIN as i64, i16::MAX as i64) as i16
+}
+
+/// Resamples interleaved PCM by a ratio of `1 + ppm / 10^6` input samples per output sample.
+///
+/// A positive ratio adjustment consumes the input faster than it is played, draining a
+/// buffer that fills up because the source clock runs fast.
+#[derive(Debug, Copy, Clone)]
+pub struct DriftResampler<const CHANNELS: usize> {
+    history: [[i16; 4]; CHANNELS],
+    /// Position of the next output past `history[1]`, in Q32 input samples.
+    phase: u64,
+    step: u64,
+    ppm: i32,
+}
+
+impl<const CHANNELS: usize> Default for DriftResampler<CHANNELS> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const CHANNELS: usize> DriftResampler<CHANNELS> {
+    /// Samples of delay through the interpolator.
+    pub const DELAY: usize = 2;
+
+    pub const fn new() -> Self {
+        Self {
+            history: [[0; 4]; CHANNELS],
+            phase: 0,
+            step: ONE,
+            ppm: 0,
+        }
+    }
+
+    pub fn ppm(&self) -> i32 {
+        self.ppm
+    }
+
+    /// Sets the ratio adjustment, clamped to ±100000 ppm.
+    pub fn set_ppm(&mut self, ppm: i32) {
+        self.ppm = ppm.clamp(-100_000, 100_000);
+        self.step = (ONE as i64 + ((ONE as i64 * self.ppm as i64) / 1_000_000)) as u64;
+    }
+
+    pub fn reset(&mut self) {
+        self.history = [[0; 4]; CHANNELS];
+        self.phase = 0;
+    }
+
+    /// Output samples per channel that `input` samples can produce at most, the room
+    /// [`DriftResampler::process`] needs.
+    pub fn max_output(&self, input: usize) -> usize {
+        (((input as u64) << 32) / self.step) as usize + 1
+    }
+
+    /// Resamples `input` into `o` and returns the number of samples per channel written.
+    pub fn process(&mut self, input: &[[i16; CHANNELS]], o: &mut [[i16; CHANNELS]]) -> usize {
+        let mut n = 0;
+        for sample in input {
+            for ch in 0..CHANNELS {
+                self.history[ch].copy_within(1.., 0);
+                self.history[ch][3] = sample[ch];
+            }
+            while self.phase < ONE {
+                let mu = (self.phase >> 16) as i64;
+                for ch in 0..CHANNELS {
+                    o[n][ch] = interpolate(&self.history[ch], mu);
+                }
+                n += 1;
+                self.phase += self.step;
+            }
+            self.phase -= ONE;
+        }
+        n
+    }
+
+    /// Interleaves and resamples a block coming out of a
+    /// [`SynthesisFilter`](crate::filter_state::SynthesisFilter).
+    pub fn process_block<const SUBBANDS: usize>(
+        &mut self,
+        block: &[[i16; SUBBANDS]; CHANNELS],
+        o: &mut [[i16; CHANNELS]],
+    ) -> usize {
+        let mut input = [[0; CHANNELS]; SUBBANDS];
+        for (i, sample) in input.iter_mut().enumerate() {
+            for ch in 0..CHANNELS {
+                sample[ch] = block[ch][i];
+            }
+        }
+        self.process(&input, o)
+    }
+}
+
+/// PI controller turning the fill level of the output buffer into a ratio adjustment for
+/// [`DriftResampler::set_ppm`].
+///
+/// The fill level is smoothed first, as it jumps by a packet at every arrival. Gains are Q16
+/// ppm per sample of error, the integral one per update.
+#[derive(Debug, Copy, Clone)]
+pub struct DriftController {
+    target: i64,
+    max_ppm: i32,
+    kp: i64,
+    ki: i64,
+    /// Smoothed fill level, Q8.
+    average: Option<i64>,
+    /// Sum of the errors, Q8.
+    integral: i64,
+    ppm: i32,
+}
+
+impl DriftController {
+    /// Steers towards `target` samples of fill, with adjustments of at most `max_ppm`.
+    pub const fn new(target: usize, max_ppm: i32) -> Self {
+        Self {
+            target: (target as i64) << 8,
+            max_ppm,
+            kp: 8 << 16,
+            ki: 16,
+            average: None,
+            integral: 0,
+            ppm: 0,
+        }
+    }
+
+    /// Replaces the default gains of 8 and 1/4096 ppm per sample.
+    pub const fn with_gains(self, kp: i32, ki: i32) -> Self {
+        Self {
+            kp: kp as i64,
+            ki: ki as i64,
+            ..self
+        }
+    }
+
+    pub fn ppm(&self) -> i32 {
+        self.ppm
+    }
+
+    pub fn reset(&mut self) {
+        self.average = None;
+        self.integral = 0;
+        self.ppm = 0;
+    }
+
+    /// Takes the current fill level in samples and returns the new ratio adjustment.
+    pub fn update(&mut self, fill: usize) -> i32 {
+        let fill = (fill as i64) << 8;
+        let average = match self.average {
+            Some(average) => average + ((fill - average) >> 4),
+            None => fill,
+        };
+        self.average = Some(average);
+        let error = average - self.target;
+        let max = self.max_ppm as i64;
+        let ppm = (self.kp * error + self.ki * (self.integral + error)) >> 24;
+        // Stop integrating while saturated so the integral does not wind up.
+        if ppm.abs() < max {
+            self.integral += error;
+        }
+        self.ppm = ppm.clamp(-max, max) as i32;
+        self.ppm
+    }
+}
+
+/// [`DriftResampler`] steered by a [`DriftController`].
+#[derive(Debug, Copy, Clone)]
+pub struct DriftCompensator<const CHANNELS: usize> {
+    resampler: DriftResampler<CHANNELS>,
+    controller: DriftController,
+}
+
+impl<const CHANNELS: usize> DriftCompensator<CHANNELS> {
+    pub const fn new(controller: DriftController) -> Self {
+        Self {
+            resampler: DriftResampler::new(),
+            controller,
+        }
+    }
+
+    pub fn resampler(&self) -> &DriftResampler<CHANNELS> {
+        &self.resampler
+    }
+
+    pub fn controller(&self) -> &DriftController {
+        &self.controller
+    }
+
+    /// Updates the ratio for `fill` samples in the output buffer, then resamples `block`
+    /// into `o`. Returns the number of samples per channel written.
+    pub fn process_block<const SUBBANDS: usize>(
+        &mut self,
+        block: &[[i16; SUBBANDS]; CHANNELS],
+        fill: usize,
+        o: &mut [[i16; CHANNELS]],
+    ) -> usize {
+        let ppm = self.controller.update(fill);
+        self.resampler.set_ppm(ppm);
+        self.resampler.process_block(block, o)
+    }
+}
@@ -0,0 +1,140 @@
+use crate::filter_state::{FilterState, SynthesisFilter};
+use crate::frame_decoder::{dequantize_sample, FrameDecodeError, FrameReader};
+use crate::header::{ChannelMode, SBCHeader, MAX_BLOCKS};
+use crate::io::ByteInput;
+use crate::sbc::{Channels, Subbands, ValidChannels, ValidSubbands};
+
+/// One block of a single channel.
+pub type MonoBlock<S, const SUBBANDS: usize> = [[S; SUBBANDS]; 1];
+
+/// Decodes the two independent programs of a dual channel frame as separate mono streams,
+/// each through its own synthesis filter.
+///
+/// A program whose filter is `None` is skipped: its samples are read past but neither
+/// dequantized nor synthesized.
+#[derive(Debug)]
+pub struct DualFrameDecoder<
+    'd,
+    'b,
+    const SUBBANDS: usize,
+    B: ByteInput,
+    F: SynthesisFilter<1, SUBBANDS> = FilterState<1, SUBBANDS>,
+> where
+    Subbands<SUBBANDS>: ValidSubbands,
+{
+    decoder: [Option<&'d mut F>; 2],
+    reader: FrameReader<'b, 2, SUBBANDS, B>,
+}
+
+impl<'d, 'b, const SUBBANDS: usize, B: ByteInput, F: SynthesisFilter<1, SUBBANDS>>
+    DualFrameDecoder<'d, 'b, SUBBANDS, B, F>
+where
+    Channels<2>: ValidChannels,
+    Subbands<SUBBANDS>: ValidSubbands,
+{
+    fn check(header: &SBCHeader) -> Result<(), FrameDecodeError> {
+        if let ChannelMode::SBC_MODE_DUAL_CHANNEL = header.channel_mode() {
+            Ok(())
+        } else {
+            Err(FrameDecodeError::SizeBed)
+        }
+    }
+
+    pub fn new_no_crc(
+        header: &SBCHeader,
+        decoder: [Option<&'d mut F>; 2],
+        buffer: &'b mut B,
+    ) -> Result<Self, FrameDecodeError> {
+        Self::check(header)?;
+        let reader = FrameReader::new_no_crc(header, buffer)?;
+        Ok(Self { decoder, reader })
+    }
+
+    pub fn new(
+        header: &SBCHeader,
+        decoder: [Option<&'d mut F>; 2],
+        buffer: &'b mut B,
+    ) -> Result<Self, FrameDecodeError> {
+        Self::check(header)?;
+        let reader = FrameReader::new(header, buffer)?;
+        Ok(Self { decoder, reader })
+    }
+
+    pub fn new_skip_crc(
+        header: &SBCHeader,
+        decoder: [Option<&'d mut F>; 2],
+        buffer: &'b mut B,
+    ) -> Result<Self, FrameDecodeError> {
+        Self::check(header)?;
+        let reader = FrameReader::new_skip_crc(header, buffer)?;
+        Ok(Self { decoder, reader })
+    }
+
+    pub fn reader(&self) -> &FrameReader<'b, 2, SUBBANDS, B> {
+        &self.reader
+    }
+
+    /// Subband samples of both programs; a skipped program reads as silence.
+    pub fn read_block(&mut self) -> Result<[[[i32; SUBBANDS]; 1]; 2], FrameDecodeError> {
+        let q = self.reader.read_quantized()?;
+        let mut sample = [[[0; SUBBANDS]; 1]; 2];
+        for ch in 0..2 {
+            if self.decoder[ch].is_none() {
+                continue;
+            }
+            for sb in 0..SUBBANDS {
+                sample[ch][0][sb] = dequantize_sample(
+                    self.reader.scale_factor()[ch][sb],
+                    self.reader.bits()[ch][sb],
+                    q[ch][sb],
+                );
+            }
+        }
+        Ok(sample)
+    }
+
+    /// Decodes one block of each program that has a filter.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(
+        &mut self,
+    ) -> Result<[Option<MonoBlock<F::Sample, SUBBANDS>>; 2], FrameDecodeError> {
+        let sample = self.read_block()?;
+        let mut o = [None, None];
+        for ch in 0..2 {
+            if let Some(decoder) = &mut self.decoder[ch] {
+                o[ch] = Some(decoder.filter(&sample[ch]));
+            }
+        }
+        Ok(o)
+    }
+
+    /// Decodes the remaining blocks of the frame into `o`, one output per program. Every
+    /// program with a filter needs an output with room for all of them.
+    /// Returns the number of blocks written.
+    pub fn decode_frame(
+        &mut self,
+        o: [Option<&mut [MonoBlock<F::Sample, SUBBANDS>]>; 2],
+    ) -> Result<usize, FrameDecodeError> {
+        let blocks = self.reader.blocks();
+        if blocks == 0 {
+            return Err(FrameDecodeError::NoBlock);
+        }
+        for ch in 0..2 {
+            if self.decoder[ch].is_some() && o[ch].as_ref().map_or(0, |o| o.len()) < blocks {
+                return Err(FrameDecodeError::SizeBed);
+            }
+        }
+        let mut sample = [[[[0_i32; SUBBANDS]; 1]; MAX_BLOCKS]; 2];
+        for b in 0..blocks {
+            let s = self.read_block()?;
+            sample[0][b] = s[0];
+            sample[1][b] = s[1];
+        }
+        for (ch, o) in o.into_iter().enumerate() {
+            if let (Some(decoder), Some(o)) = (&mut self.decoder[ch], o) {
+                decoder.filter_frame(&sample[ch][..blocks], &mut o[..blocks]);
+            }
+        }
+        Ok(blocks)
+    }
+}
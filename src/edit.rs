@@ -0,0 +1,156 @@
+//! Lossless editing of SBC streams on frame boundaries. Frames are copied byte for byte, so
+//! their CRCs stay valid.
+
+use crate::header::SBCHeader;
+use crate::io::{ByteError, ByteOutput};
+use core::ops::Range;
+
+#[derive(Debug)]
+pub enum EditError {
+    ByteError(ByteError),
+    /// No syncword where a frame should start, or a truncated last frame.
+    BadFrame,
+    /// The streams do not share the same header configuration.
+    Incompatible,
+    /// A frame index or sample position past the end of the stream.
+    OutOfRange,
+}
+
+impl From<ByteError> for EditError {
+    fn from(value: ByteError) -> Self {
+        Self::ByteError(value)
+    }
+}
+
+/// Iterates over the frames of a stream, yielding each header with the bytes of its frame.
+#[derive(Debug, Clone)]
+pub struct Frames<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Frames<'a> {
+    /// The bytes not iterated yet.
+    pub fn remaining(&self) -> &'a [u8] {
+        self.data
+    }
+}
+
+impl<'a> Iterator for Frames<'a> {
+    type Item = Result<(SBCHeader, &'a [u8]), EditError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.is_empty() {
+            return None;
+        }
+        let header = match self
+            .data
+            .first_chunk::<3>()
+            .and_then(SBCHeader::decode_array)
+        {
+            Some(header) => header,
+            None => {
+                self.data = &[];
+                return Some(Err(EditError::BadFrame));
+            }
+        };
+        let len = header.frame_length();
+        if len > self.data.len() {
+            self.data = &[];
+            return Some(Err(EditError::BadFrame));
+        }
+        let (frame, rest) = self.data.split_at(len);
+        self.data = rest;
+        Some(Ok((header, frame)))
+    }
+}
+
+pub fn frames(stream: &[u8]) -> Frames<'_> {
+    Frames { data: stream }
+}
+
+/// Byte offset of frame `index`. `index` may be the number of frames, giving the length of
+/// the stream.
+pub fn frame_offset(stream: &[u8], index: usize) -> Result<usize, EditError> {
+    let mut it = frames(stream);
+    for _ in 0..index {
+        it.next().ok_or(EditError::OutOfRange)??;
+    }
+    Ok(stream.len() - it.remaining().len())
+}
+
+/// Splits the stream before frame `index`.
+pub fn split_at_frame(stream: &[u8], index: usize) -> Result<(&[u8], &[u8]), EditError> {
+    Ok(stream.split_at(frame_offset(stream, index)?))
+}
+
+/// Splits the stream before the frame holding PCM sample `sample`, counted per channel from
+/// the start of the stream. A `sample` just past the last frame splits off the whole stream.
+pub fn split_at_sample(stream: &[u8], sample: u64) -> Result<(&[u8], &[u8]), EditError> {
+    let mut position = 0;
+    let mut it = frames(stream);
+    loop {
+        let offset = stream.len() - it.remaining().len();
+        match it.next() {
+            None if position == sample => return Ok(stream.split_at(offset)),
+            None => return Err(EditError::OutOfRange),
+            Some(frame) => {
+                let (header, _) = frame?;
+                let next = position + header.frame_samples() as u64;
+                if sample < next {
+                    return Ok(stream.split_at(offset));
+                }
+                position = next;
+            }
+        }
+    }
+}
+
+/// Writes `streams` one after another. Where one stream ends and the next starts, the frames
+/// must have the [same configuration](SBCHeader::same_configuration), the bitpool may differ;
+/// this is checked before anything is written. Returns the number of bytes written.
+pub fn concat<O: ByteOutput>(streams: &[&[u8]], output: &mut O) -> Result<usize, EditError> {
+    let mut last: Option<SBCHeader> = None;
+    for stream in streams {
+        let mut first = true;
+        for frame in frames(stream) {
+            let (header, _) = frame?;
+            if first && last.is_some_and(|last| !last.same_configuration(&header)) {
+                return Err(EditError::Incompatible);
+            }
+            first = false;
+            last = Some(header);
+        }
+    }
+    let len = streams.iter().map(|stream| stream.len()).sum();
+    if output.remaining().is_some_and(|n| n < len) {
+        return Err(EditError::ByteError(()));
+    }
+    for stream in streams {
+        output.write(stream)?;
+    }
+    Ok(len)
+}
+
+/// Writes the stream without the frames in `range`. Returns the number of bytes written.
+pub fn drop_frames<O: ByteOutput>(
+    stream: &[u8],
+    range: Range<usize>,
+    output: &mut O,
+) -> Result<usize, EditError> {
+    if range.start > range.end {
+        return Err(EditError::OutOfRange);
+    }
+    let start = frame_offset(stream, range.start)?;
+    let end = start + frame_offset(&stream[start..], range.end - range.start)?;
+    // Reject trailing garbage too, the result has to stay a valid stream.
+    for frame in frames(&stream[end..]) {
+        frame?;
+    }
+    let len = stream.len() - (end - start);
+    if output.remaining().is_some_and(|n| n < len) {
+        return Err(EditError::ByteError(()));
+    }
+    output.write(&stream[..start])?;
+    output.write(&stream[end..])?;
+    Ok(len)
+}
@@ -0,0 +1,257 @@
+//! PCM to SBC encoding: analysis, scale factors, bit allocation and quantization, written out
+//! with [`write_frame`].
+
+use crate::analysis::AnalysisFilter;
+use crate::frame_decoder::{dequantize_sample, FrameDecodeError, SBCDEC_FIXED_EXTRA_BITS};
+use crate::frame_encoder::write_frame;
+use crate::header::{AllocationMethod, ChannelMode, SBCHeader, MAX_BLOCKS};
+use crate::io::ByteOutput;
+use crate::sbc::{calculate_bits, codec_delay, Channels, Subbands, ValidChannels, ValidSubbands};
+
+/// Smallest scale factor whose range holds `peak`, in subband sample units.
+pub(crate) fn scale_factor(peak: u32) -> u8 {
+    let mut sf = 0;
+    while sf < 15 && peak >= 1 << (sf + 1 + SBCDEC_FIXED_EXTRA_BITS) {
+        sf += 1;
+    }
+    sf
+}
+
+/// Inverse of [`dequantize_sample`](crate::frame_decoder::dequantize_sample): the level of
+/// `2^bits - 1` whose reconstruction interval holds `sample`.
+pub(crate) fn quantize_sample(scale_factor: u8, bits: u8, sample: i32) -> u16 {
+    if bits == 0 {
+        return 0;
+    }
+    let shift = scale_factor + 1 + SBCDEC_FIXED_EXTRA_BITS;
+    let levels = (1_i64 << bits) - 1;
+    let q = ((sample as i64 + (1 << shift)) * levels) >> (shift + 1);
+    q.clamp(0, levels - 1) as u16
+}
+
+/// Chooses mid/side coding per subband of a stereo frame and converts the chosen subbands of
+/// `samples` in place, returning the joint bitmask with subband `sb` in bit `sb`.
+///
+/// A subband goes mid/side when the scale factors of `M = (L + R) / 2` and `S = (L - R) / 2`
+/// add up to less than those of `L` and `R`. As in other encoders, the last subband always
+/// stays left/right. [`FrameDecoder`](crate::frame_decoder::FrameDecoder) turns it back into
+/// `L = M + S` and `R = M - S`.
+pub fn joint_stereo<const CHANNELS: usize, const SUBBANDS: usize>(
+    samples: &mut [[[i32; SUBBANDS]; CHANNELS]],
+) -> u8 {
+    if CHANNELS != 2 {
+        return 0;
+    }
+    let mut joint = 0;
+    for sb in 0..SUBBANDS - 1 {
+        let mut peak = [0; 4];
+        for s in samples.iter() {
+            let (l, r) = (s[0][sb], s[1][sb]);
+            for (peak, v) in peak.iter_mut().zip([l, r, (l + r) >> 1, (l - r) >> 1]) {
+                *peak = v.unsigned_abs().max(*peak);
+            }
+        }
+        let [l, r, m, s] = peak.map(scale_factor);
+        if m + s < l + r {
+            joint |= 1 << sb;
+            for s in samples.iter_mut() {
+                let (l, r) = (s[0][sb], s[1][sb]);
+                s[0][sb] = (l + r) >> 1;
+                s[1][sb] = (l - r) >> 1;
+            }
+        }
+    }
+    joint
+}
+
+/// Squared error left by quantizing `samples` with `scale_factor` and `bits`, summed over the
+/// frame, in squared subband sample units.
+pub fn quantization_noise<const CHANNELS: usize, const SUBBANDS: usize>(
+    samples: &[[[i32; SUBBANDS]; CHANNELS]],
+    scale_factor: &[[u8; SUBBANDS]; CHANNELS],
+    bits: &[[u8; SUBBANDS]; CHANNELS],
+) -> u64 {
+    let mut noise = 0;
+    for s in samples {
+        for ch in 0..CHANNELS {
+            for sb in 0..SUBBANDS {
+                let (sf, bits, v) = (scale_factor[ch][sb], bits[ch][sb], s[ch][sb]);
+                let e = v - dequantize_sample(sf, bits, quantize_sample(sf, bits, v));
+                noise += (e as i64 * e as i64) as u64;
+            }
+        }
+    }
+    noise
+}
+
+/// What an [`Encoder`] did so far.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct EncoderStats {
+    pub frames: u32,
+    /// Frames encoded with [`AllocationMethod::SBC_AM_SNR`].
+    pub snr: u32,
+    /// Frames encoded with [`AllocationMethod::SBC_AM_LOUDNESS`].
+    pub loudness: u32,
+    /// Subbands coded as mid/side, summed over the frames.
+    pub joint_subbands: u32,
+    /// Sum of [`quantization_noise`] over the frames.
+    pub noise: u64,
+}
+
+/// Encodes interleaved PCM, one frame of [`SBCHeader::frame_samples`] samples per channel at
+/// a time.
+///
+/// The decoded output lags the input by [`Encoder::delay`] samples. Appending
+/// [`Gapless::padding_blocks`](crate::gapless::Gapless::padding_blocks) blocks of silence gets
+/// the whole input out of the decoder.
+#[derive(Debug, Copy, Clone)]
+pub struct Encoder<const CHANNELS: usize, const SUBBANDS: usize>
+where
+    Channels<CHANNELS>: ValidChannels,
+    Subbands<SUBBANDS>: ValidSubbands,
+{
+    header: SBCHeader,
+    analysis: AnalysisFilter<CHANNELS, SUBBANDS>,
+    adaptive_allocation: bool,
+    stats: EncoderStats,
+}
+
+impl<const CHANNELS: usize, const SUBBANDS: usize> Encoder<CHANNELS, SUBBANDS>
+where
+    Channels<CHANNELS>: ValidChannels,
+    Subbands<SUBBANDS>: ValidSubbands,
+{
+    pub fn new(header: &SBCHeader) -> Result<Self, FrameDecodeError> {
+        if header.channel_mode().channels() != CHANNELS
+            || header.subbands().subbands() != SUBBANDS
+            || header.bitpool() > header.max_bitpool()
+        {
+            return Err(FrameDecodeError::SizeBed);
+        }
+        Ok(Self {
+            header: *header,
+            analysis: AnalysisFilter::new(),
+            adaptive_allocation: false,
+            stats: EncoderStats::default(),
+        })
+    }
+
+    pub fn header(&self) -> &SBCHeader {
+        &self.header
+    }
+
+    /// Switches to `bitpool` from the next frame on.
+    pub fn set_bitpool(&mut self, bitpool: u8) -> Result<(), FrameDecodeError> {
+        match self.header.with_bitpool(bitpool) {
+            Some(header) if bitpool <= header.max_bitpool() => {
+                self.header = header;
+                Ok(())
+            }
+            _ => Err(FrameDecodeError::SizeBed),
+        }
+    }
+
+    pub fn adaptive_allocation(&self) -> bool {
+        self.adaptive_allocation
+    }
+
+    /// Lets every frame use whichever allocation method gives it the least
+    /// [`quantization_noise`], instead of the one in the header. Plain squared error favours
+    /// SNR allocation on most frames; [`EncoderStats`] counts the choices. mSBC always uses
+    /// loudness.
+    pub fn set_adaptive_allocation(&mut self, adaptive: bool) {
+        self.adaptive_allocation = adaptive;
+    }
+
+    pub fn stats(&self) -> &EncoderStats {
+        &self.stats
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats = EncoderStats::default();
+    }
+
+    /// Samples per channel of one frame.
+    pub fn frame_samples(&self) -> usize {
+        self.header.frame_samples()
+    }
+
+    /// Algorithmic delay of analysis and synthesis together, in samples.
+    pub fn delay(&self) -> usize {
+        codec_delay(SUBBANDS)
+    }
+
+    pub fn reset(&mut self) {
+        self.analysis.reset();
+    }
+
+    /// Encodes one frame of `pcm` into `output` and returns its length in bytes.
+    pub fn encode<O: ByteOutput>(
+        &mut self,
+        pcm: &[[i16; CHANNELS]],
+        output: &mut O,
+    ) -> Result<usize, FrameDecodeError> {
+        if pcm.len() != self.frame_samples() {
+            return Err(FrameDecodeError::SizeBed);
+        }
+        let blocks = pcm.len() / SUBBANDS;
+        let mut samples = [[[0; SUBBANDS]; CHANNELS]; MAX_BLOCKS];
+        let samples = &mut samples[..blocks];
+        for (s, pcm) in samples.iter_mut().zip(pcm.chunks_exact(SUBBANDS)) {
+            let mut block = [[0; SUBBANDS]; CHANNELS];
+            for (i, sample) in pcm.iter().enumerate() {
+                for ch in 0..CHANNELS {
+                    block[ch][i] = sample[ch];
+                }
+            }
+            *s = self.analysis.filter(&block);
+        }
+
+        let joint = match self.header.channel_mode() {
+            ChannelMode::SBC_MODE_JOINT_STEREO => joint_stereo(samples),
+            _ => 0,
+        };
+        let mut sf = [[0; SUBBANDS]; CHANNELS];
+        for ch in 0..CHANNELS {
+            for sb in 0..SUBBANDS {
+                let peak = samples.iter().map(|s| s[ch][sb].unsigned_abs()).max();
+                sf[ch][sb] = scale_factor(peak.unwrap_or(0));
+            }
+        }
+        let mut header = self.header;
+        let mut bits = calculate_bits(&header, &sf)?.bits;
+        let mut noise = quantization_noise(samples, &sf, &bits);
+        if self.adaptive_allocation {
+            let other = match header.allocation_method() {
+                AllocationMethod::SBC_AM_LOUDNESS => AllocationMethod::SBC_AM_SNR,
+                AllocationMethod::SBC_AM_SNR => AllocationMethod::SBC_AM_LOUDNESS,
+            };
+            if let Some(other) = header.with_allocation_method(other) {
+                let other_bits = calculate_bits(&other, &sf)?.bits;
+                let other_noise = quantization_noise(samples, &sf, &other_bits);
+                if other_noise < noise {
+                    (header, bits, noise) = (other, other_bits, other_noise);
+                }
+            }
+        }
+        let mut quantized = [[[0; SUBBANDS]; CHANNELS]; MAX_BLOCKS];
+        let quantized = &mut quantized[..blocks];
+        for (q, s) in quantized.iter_mut().zip(samples.iter()) {
+            for ch in 0..CHANNELS {
+                for sb in 0..SUBBANDS {
+                    q[ch][sb] = quantize_sample(sf[ch][sb], bits[ch][sb], s[ch][sb]);
+                }
+            }
+        }
+        write_frame(&header, joint, &sf, &bits, quantized, output)?;
+
+        self.stats.frames += 1;
+        match header.allocation_method() {
+            AllocationMethod::SBC_AM_SNR => self.stats.snr += 1,
+            AllocationMethod::SBC_AM_LOUDNESS => self.stats.loudness += 1,
+        }
+        self.stats.joint_subbands += joint.count_ones();
+        self.stats.noise += noise;
+        Ok(header.frame_length())
+    }
+}
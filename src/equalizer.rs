@@ -0,0 +1,191 @@
+use crate::filter_state::SynthesisFilter;
+use crate::gain::{apply_gain, db_to_gain_max, Ramp, GAIN_ONE};
+use crate::header::{Frequency, MAX_BLOCKS};
+
+/// Largest boost of [`SubbandEqualizer::set_gains_db`], before headroom.
+pub const MAX_BOOST_DB: f32 = 24.0;
+
+/// Tone curves for [`SubbandEqualizer::set_preset`], sampled at the subband centre frequencies.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum EqPreset {
+    #[default]
+    Flat,
+    BassBoost,
+    TrebleBoost,
+    /// Bass and treble boost.
+    Loudness,
+    /// Keeps the telephone band and cuts the rest.
+    Voice,
+}
+
+impl EqPreset {
+    /// `(Hz, dB)` corners, interpolated linearly and held flat past both ends.
+    const fn curve(self) -> &'static [(f32, f32)] {
+        match self {
+            Self::Flat => &[(0.0, 0.0)],
+            Self::BassBoost => &[(1000.0, 6.0), (4000.0, 0.0)],
+            Self::TrebleBoost => &[(4000.0, 0.0), (10000.0, 6.0)],
+            Self::Loudness => &[(1000.0, 6.0), (4000.0, 0.0), (10000.0, 4.0)],
+            Self::Voice => &[(300.0, -6.0), (1000.0, 0.0), (3400.0, 0.0), (6000.0, -9.0)],
+        }
+    }
+
+    /// Gain of the curve at `hz` in dB.
+    pub fn gain_db(self, hz: f32) -> f32 {
+        let c = self.curve();
+        if hz <= c[0].0 {
+            return c[0].1;
+        }
+        for w in c.windows(2) {
+            let ((f0, g0), (f1, g1)) = (w[0], w[1]);
+            if hz <= f1 {
+                return g0 + (g1 - g0) * (hz - f0) / (f1 - f0);
+            }
+        }
+        c[c.len() - 1].1
+    }
+}
+
+/// Centre frequency in Hz of subband `sb` out of `subbands` at `frequency`.
+pub fn band_center(frequency: Frequency, subbands: usize, sb: usize) -> f32 {
+    (sb as f32 + 0.5) * frequency.frequency() as f32 / (2 * subbands) as f32
+}
+
+/// Applies a per-subband gain, shared by all channels, to the subband samples before they
+/// reach `F`.
+///
+/// With headroom enabled, all bands are scaled down by the largest boost so that no band gains
+/// more than unity and a full-scale stream does not start clipping in the output saturation.
+/// Without it, boosts are clamped to unity, like in [`SubbandGain`](crate::gain::SubbandGain),
+/// and only cuts apply.
+#[derive(Debug)]
+pub struct SubbandEqualizer<F, const SUBBANDS: usize> {
+    filter: F,
+    target: [i32; SUBBANDS],
+    headroom: bool,
+    ramp: [Ramp; SUBBANDS],
+}
+
+impl<F, const SUBBANDS: usize> SubbandEqualizer<F, SUBBANDS> {
+    pub const fn new(filter: F) -> Self {
+        Self {
+            filter,
+            target: [GAIN_ONE; SUBBANDS],
+            headroom: true,
+            ramp: [Ramp::new(GAIN_ONE); SUBBANDS],
+        }
+    }
+
+    pub fn inner(&self) -> &F {
+        &self.filter
+    }
+
+    pub fn inner_mut(&mut self) -> &mut F {
+        &mut self.filter
+    }
+
+    pub fn into_inner(self) -> F {
+        self.filter
+    }
+
+    /// Requested gains in Q16, before headroom.
+    pub fn gains(&self) -> &[i32; SUBBANDS] {
+        &self.target
+    }
+
+    /// Gain currently applied to subband `sb` in Q16, including headroom.
+    pub fn gain(&self, sb: usize) -> i32 {
+        self.ramp[sb].gain()
+    }
+
+    pub fn headroom(&self) -> bool {
+        self.headroom
+    }
+
+    pub fn set_headroom(&mut self, headroom: bool, ramp_blocks: u16) {
+        self.headroom = headroom;
+        self.update(ramp_blocks);
+    }
+
+    /// Moves the Q16 gains of all subbands to `gains` over `ramp_blocks` blocks.
+    pub fn set_gains(&mut self, gains: &[i32; SUBBANDS], ramp_blocks: u16) {
+        self.target = *gains;
+        self.update(ramp_blocks);
+    }
+
+    /// Moves the gains of all subbands to `db`, at most [`MAX_BOOST_DB`], over `ramp_blocks`
+    /// blocks.
+    pub fn set_gains_db(&mut self, db: &[f32; SUBBANDS], ramp_blocks: u16) {
+        let mut gains = [0; SUBBANDS];
+        for sb in 0..SUBBANDS {
+            gains[sb] = db_to_gain_max(db[sb], MAX_BOOST_DB);
+        }
+        self.set_gains(&gains, ramp_blocks);
+    }
+
+    /// Moves the gains to `preset` sampled at the band centres of a `frequency` stream.
+    pub fn set_preset(&mut self, preset: EqPreset, frequency: Frequency, ramp_blocks: u16) {
+        let mut db = [0.0; SUBBANDS];
+        for sb in 0..SUBBANDS {
+            db[sb] = preset.gain_db(band_center(frequency, SUBBANDS, sb));
+        }
+        self.set_gains_db(&db, ramp_blocks);
+    }
+
+    fn update(&mut self, ramp_blocks: u16) {
+        let max = self.target.iter().copied().max().unwrap_or(GAIN_ONE);
+        for sb in 0..SUBBANDS {
+            let gain = if self.headroom && max > GAIN_ONE {
+                ((self.target[sb] as i64 * GAIN_ONE as i64) / max as i64) as i32
+            } else {
+                self.target[sb]
+            };
+            let gain = gain.clamp(0, GAIN_ONE);
+            self.ramp[sb].set(gain, ramp_blocks);
+        }
+    }
+
+    fn apply<const CHANNELS: usize>(
+        &mut self,
+        s: &[[i32; SUBBANDS]; CHANNELS],
+    ) -> [[i32; SUBBANDS]; CHANNELS] {
+        let mut o = *s;
+        for sb in 0..SUBBANDS {
+            let gain = self.ramp[sb].next();
+            if gain == GAIN_ONE {
+                continue;
+            }
+            for ch in 0..CHANNELS {
+                o[ch][sb] = apply_gain(s[ch][sb], gain);
+            }
+        }
+        o
+    }
+}
+
+impl<F, const CHANNELS: usize, const SUBBANDS: usize> SynthesisFilter<CHANNELS, SUBBANDS>
+    for SubbandEqualizer<F, SUBBANDS>
+where
+    F: SynthesisFilter<CHANNELS, SUBBANDS>,
+{
+    type Sample = F::Sample;
+
+    fn filter(&mut self, s: &[[i32; SUBBANDS]; CHANNELS]) -> [[F::Sample; SUBBANDS]; CHANNELS] {
+        let s = self.apply(s);
+        self.filter.filter(&s)
+    }
+
+    fn filter_frame(
+        &mut self,
+        s: &[[[i32; SUBBANDS]; CHANNELS]],
+        o: &mut [[[F::Sample; SUBBANDS]; CHANNELS]],
+    ) {
+        for (s, o) in s.chunks(MAX_BLOCKS).zip(o.chunks_mut(MAX_BLOCKS)) {
+            let mut t = [[[0; SUBBANDS]; CHANNELS]; MAX_BLOCKS];
+            for b in 0..s.len() {
+                t[b] = self.apply(&s[b]);
+            }
+            self.filter.filter_frame(&t[..s.len()], o);
+        }
+    }
+}
@@ -1,7 +1,34 @@
+use crate::io::{ByteError, ByteInput, ByteOutput};
 use crate::sbc::FILTER_ORDER;
 use crate::sbc::{Channels, Subbands, ValidChannels, ValidSubbands};
 use core::fmt::{Debug, Formatter};
 
+/// Largest [`FilterState::snapshot_len`] of any configuration, also enough for
+/// [`FrameFilterState::snapshot_len`](crate::frame_filter_state::FrameFilterState::snapshot_len).
+pub const MAX_SNAPSHOT_LEN: usize = 3 + 2 * 8 * FILTER_ORDER * 4;
+
+pub trait SynthesisFilter<const CHANNELS: usize, const SUBBANDS: usize> {
+    type Sample: Copy;
+
+    fn filter(&mut self, s: &[[i32; SUBBANDS]; CHANNELS]) -> [[Self::Sample; SUBBANDS]; CHANNELS];
+
+    /// Algorithmic delay in samples, see [`codec_delay`](crate::sbc::codec_delay).
+    fn delay(&self) -> usize {
+        crate::sbc::codec_delay(SUBBANDS)
+    }
+
+    fn filter_frame(
+        &mut self,
+        s: &[[[i32; SUBBANDS]; CHANNELS]],
+        o: &mut [[[Self::Sample; SUBBANDS]; CHANNELS]],
+    ) {
+        for (s, o) in s.iter().zip(o.iter_mut()) {
+            *o = self.filter(s);
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
 pub struct FilterState<const CHANNELS: usize, const SUBBANDS: usize>
 where
     Channels<CHANNELS>: ValidChannels,
@@ -23,6 +50,58 @@ where
         }
     }
 
+    /// Clears the history, as if nothing had been decoded yet.
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    /// Length in bytes of [`FilterState::snapshot`].
+    pub const fn snapshot_len() -> usize {
+        3 + CHANNELS * SUBBANDS * FILTER_ORDER * 4
+    }
+
+    /// Writes the state as `CHANNELS`, `SUBBANDS` and the history position in one byte each,
+    /// followed by the history as little-endian `i32`, channel by channel and subband by
+    /// subband.
+    pub fn snapshot<O: ByteOutput>(&self, output: &mut O) -> Result<(), ByteError> {
+        if output.remaining().is_some_and(|n| n < Self::snapshot_len()) {
+            return Err(());
+        }
+        output.write(&[CHANNELS as u8, SUBBANDS as u8, self.step])?;
+        for ch in 0..CHANNELS {
+            for sb in 0..SUBBANDS {
+                for v in self.filter_state[ch][sb] {
+                    output.write(&v.to_le_bytes())?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads a state written by [`FilterState::snapshot`] for the same configuration.
+    pub fn restore<B: ByteInput>(input: &mut B) -> Result<Self, ByteError> {
+        let mut head = [0; 3];
+        input.read(&mut head)?;
+        if head[0] as usize != CHANNELS
+            || head[1] as usize != SUBBANDS
+            || head[2] as usize >= FILTER_ORDER
+        {
+            return Err(());
+        }
+        let mut state = Self::new();
+        state.step = head[2];
+        for ch in 0..CHANNELS {
+            for sb in 0..SUBBANDS {
+                for v in state.filter_state[ch][sb].iter_mut() {
+                    let mut b = [0; 4];
+                    input.read(&mut b)?;
+                    *v = i32::from_le_bytes(b);
+                }
+            }
+        }
+        Ok(state)
+    }
+
     pub fn filter(&mut self, s: &[[i32; SUBBANDS]; CHANNELS]) -> [[i16; SUBBANDS]; CHANNELS] {
         let step = self.step as usize;
 
@@ -45,6 +124,29 @@ where
     }
 }
 
+impl<const CHANNELS: usize, const SUBBANDS: usize> SynthesisFilter<CHANNELS, SUBBANDS>
+    for FilterState<CHANNELS, SUBBANDS>
+where
+    Channels<CHANNELS>: ValidChannels,
+    Subbands<SUBBANDS>: ValidSubbands,
+{
+    type Sample = i16;
+
+    fn filter(&mut self, s: &[[i32; SUBBANDS]; CHANNELS]) -> [[i16; SUBBANDS]; CHANNELS] {
+        FilterState::filter(self, s)
+    }
+}
+
+impl<const CHANNELS: usize, const SUBBANDS: usize> Default for FilterState<CHANNELS, SUBBANDS>
+where
+    Channels<CHANNELS>: ValidChannels,
+    Subbands<SUBBANDS>: ValidSubbands,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<const CHANNELS: usize, const SUBBANDS: usize> Debug for FilterState<CHANNELS, SUBBANDS>
 where
     Channels<CHANNELS>: ValidChannels,
@@ -59,3 +161,76 @@ where
         s.finish()
     }
 }
+
+/// Serialized as the bytes of [`FilterState::snapshot`].
+#[cfg(feature = "serde")]
+impl<const CHANNELS: usize, const SUBBANDS: usize> serde::Serialize
+    for FilterState<CHANNELS, SUBBANDS>
+where
+    Channels<CHANNELS>: ValidChannels,
+    Subbands<SUBBANDS>: ValidSubbands,
+{
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut buf = [0; MAX_SNAPSHOT_LEN];
+        let len = Self::snapshot_len();
+        self.snapshot(&mut &mut buf[..len])
+            .map_err(|_| serde::ser::Error::custom("snapshot"))?;
+        serializer.serialize_bytes(&buf[..len])
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, const CHANNELS: usize, const SUBBANDS: usize> serde::Deserialize<'de>
+    for FilterState<CHANNELS, SUBBANDS>
+where
+    Channels<CHANNELS>: ValidChannels,
+    Subbands<SUBBANDS>: ValidSubbands,
+{
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor<const CHANNELS: usize, const SUBBANDS: usize>;
+
+        impl<'de, const CHANNELS: usize, const SUBBANDS: usize> serde::de::Visitor<'de>
+            for Visitor<CHANNELS, SUBBANDS>
+        where
+            Channels<CHANNELS>: ValidChannels,
+            Subbands<SUBBANDS>: ValidSubbands,
+        {
+            type Value = FilterState<CHANNELS, SUBBANDS>;
+
+            fn expecting(&self, f: &mut Formatter) -> core::fmt::Result {
+                write!(
+                    f,
+                    "{} bytes of FilterState snapshot",
+                    FilterState::<CHANNELS, SUBBANDS>::snapshot_len()
+                )
+            }
+
+            fn visit_bytes<E: serde::de::Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+                let mut input = v;
+                match FilterState::restore(&mut input) {
+                    Ok(state) if input.is_empty() => Ok(state),
+                    _ => Err(E::invalid_value(serde::de::Unexpected::Bytes(v), &self)),
+                }
+            }
+
+            fn visit_seq<A: serde::de::SeqAccess<'de>>(
+                self,
+                mut seq: A,
+            ) -> Result<Self::Value, A::Error> {
+                let mut buf = [0; MAX_SNAPSHOT_LEN];
+                let len = FilterState::<CHANNELS, SUBBANDS>::snapshot_len();
+                for i in 0..len {
+                    buf[i] = seq
+                        .next_element()?
+                        .ok_or_else(|| serde::de::Error::invalid_length(i, &self))?;
+                }
+                if seq.next_element::<u8>()?.is_some() {
+                    return Err(serde::de::Error::invalid_length(len + 1, &self));
+                }
+                self.visit_bytes(&buf[..len])
+            }
+        }
+
+        deserializer.deserialize_bytes(Visitor)
+    }
+}
@@ -0,0 +1,159 @@
+use crate::filter_state::SynthesisFilter;
+use crate::frame_decoder::{dequantize, FrameDecodeError, FrameReader};
+use crate::frame_encoder::write_frame;
+use crate::header::{SBCHeader, MAX_BLOCKS};
+use crate::io::{ByteInput, ByteOutput};
+use crate::meter::SubbandLevels;
+use crate::sbc::{Channels, Subbands, ValidChannels, ValidSubbands};
+
+/// A fully parsed frame: side information, quantized and dequantized subband samples.
+#[derive(Debug, Copy, Clone)]
+pub struct Frame<const CHANNELS: usize, const SUBBANDS: usize>
+where
+    Channels<CHANNELS>: ValidChannels,
+    Subbands<SUBBANDS>: ValidSubbands,
+{
+    header: SBCHeader,
+    joint: u8,
+    scale_factor: [[u8; SUBBANDS]; CHANNELS],
+    bits: [[u8; SUBBANDS]; CHANNELS],
+    blocks: usize,
+    quantized: [[[u16; SUBBANDS]; CHANNELS]; MAX_BLOCKS],
+    sample: [[[i32; SUBBANDS]; CHANNELS]; MAX_BLOCKS],
+}
+
+impl<const CHANNELS: usize, const SUBBANDS: usize> Frame<CHANNELS, SUBBANDS>
+where
+    Channels<CHANNELS>: ValidChannels,
+    Subbands<SUBBANDS>: ValidSubbands,
+{
+    pub fn from_reader<B: ByteInput>(
+        header: &SBCHeader,
+        mut reader: FrameReader<'_, CHANNELS, SUBBANDS, B>,
+    ) -> Result<Self, FrameDecodeError> {
+        let mut frame = Self {
+            header: *header,
+            joint: reader.joint(),
+            scale_factor: *reader.scale_factor(),
+            bits: *reader.bits(),
+            blocks: reader.blocks(),
+            quantized: [[[0; SUBBANDS]; CHANNELS]; MAX_BLOCKS],
+            sample: [[[0; SUBBANDS]; CHANNELS]; MAX_BLOCKS],
+        };
+        for b in 0..frame.blocks {
+            frame.quantized[b] = reader.read_quantized()?;
+            frame.sample[b] = reader.dequantize(&frame.quantized[b]);
+        }
+        Ok(frame)
+    }
+
+    /// Builds a frame from its side information and quantized samples, which are dequantized.
+    pub(crate) fn from_parts(
+        header: &SBCHeader,
+        joint: u8,
+        scale_factor: &[[u8; SUBBANDS]; CHANNELS],
+        bits: &[[u8; SUBBANDS]; CHANNELS],
+        quantized: &[[[u16; SUBBANDS]; CHANNELS]],
+    ) -> Self {
+        let mut frame = Self {
+            header: *header,
+            joint,
+            scale_factor: *scale_factor,
+            bits: *bits,
+            blocks: quantized.len(),
+            quantized: [[[0; SUBBANDS]; CHANNELS]; MAX_BLOCKS],
+            sample: [[[0; SUBBANDS]; CHANNELS]; MAX_BLOCKS],
+        };
+        for (b, q) in quantized.iter().enumerate() {
+            frame.quantized[b] = *q;
+            frame.sample[b] = dequantize(joint, scale_factor, bits, q);
+        }
+        frame
+    }
+
+    pub fn decode_no_crc<B: ByteInput>(
+        header: &SBCHeader,
+        buffer: &mut B,
+    ) -> Result<Self, FrameDecodeError> {
+        Self::from_reader(header, FrameReader::new_no_crc(header, buffer)?)
+    }
+
+    pub fn decode<B: ByteInput>(
+        header: &SBCHeader,
+        buffer: &mut B,
+    ) -> Result<Self, FrameDecodeError> {
+        Self::from_reader(header, FrameReader::new(header, buffer)?)
+    }
+
+    pub fn decode_skip_crc<B: ByteInput>(
+        header: &SBCHeader,
+        buffer: &mut B,
+    ) -> Result<Self, FrameDecodeError> {
+        Self::from_reader(header, FrameReader::new_skip_crc(header, buffer)?)
+    }
+
+    pub fn header(&self) -> &SBCHeader {
+        &self.header
+    }
+
+    /// Bit `sb` is set when subband `sb` is mid/side coded.
+    pub fn joint(&self) -> u8 {
+        self.joint
+    }
+
+    pub fn scale_factor(&self) -> &[[u8; SUBBANDS]; CHANNELS] {
+        &self.scale_factor
+    }
+
+    pub fn bits(&self) -> &[[u8; SUBBANDS]; CHANNELS] {
+        &self.bits
+    }
+
+    pub fn blocks(&self) -> usize {
+        self.blocks
+    }
+
+    pub fn quantized(&self) -> &[[[u16; SUBBANDS]; CHANNELS]] {
+        &self.quantized[..self.blocks]
+    }
+
+    /// Dequantized subband samples, after the mid/side to left/right conversion.
+    pub fn samples(&self) -> &[[[i32; SUBBANDS]; CHANNELS]] {
+        &self.sample[..self.blocks]
+    }
+
+    /// Levels of the dequantized subband samples.
+    pub fn levels(&self) -> SubbandLevels<CHANNELS, SUBBANDS> {
+        SubbandLevels::from_samples(self.samples())
+    }
+
+    /// Levels estimated from the scale factors, see [`SubbandLevels::from_scale_factors`].
+    pub fn scale_factor_levels(&self) -> SubbandLevels<CHANNELS, SUBBANDS> {
+        SubbandLevels::from_scale_factors(&self.scale_factor)
+    }
+
+    /// Writes the frame back out, header and CRC included.
+    pub fn encode<O: ByteOutput>(&self, output: &mut O) -> Result<(), FrameDecodeError> {
+        write_frame(
+            &self.header,
+            self.joint,
+            &self.scale_factor,
+            &self.bits,
+            self.quantized(),
+            output,
+        )
+    }
+
+    /// Runs `decoder` over the subband samples. Returns the number of blocks written to `o`.
+    pub fn synthesize<F: SynthesisFilter<CHANNELS, SUBBANDS>>(
+        &self,
+        decoder: &mut F,
+        o: &mut [[[F::Sample; SUBBANDS]; CHANNELS]],
+    ) -> Result<usize, FrameDecodeError> {
+        if o.len() < self.blocks {
+            return Err(FrameDecodeError::SizeBed);
+        }
+        decoder.filter_frame(self.samples(), &mut o[..self.blocks]);
+        Ok(self.blocks)
+    }
+}
@@ -1,12 +1,11 @@
 use crate::crc::crc8;
-use crate::filter_state::FilterState;
-use crate::header::{Blocks, ChannelMode, SBCHeader, MSBC_BLOCKS};
+use crate::filter_state::{FilterState, SynthesisFilter};
+use crate::header::{Blocks, ChannelMode, SBCHeader, MAX_BLOCKS, MSBC_BLOCKS};
 use crate::io::{BitInput, ByteError, ByteInput};
+use crate::sbc;
 use crate::sbc::{Channels, Subbands, ValidChannels, ValidSubbands};
-use crate::{const_for, sbc};
-use crunchy::unroll;
 
-const SBCDEC_FIXED_EXTRA_BITS: u8 = 2;
+pub(crate) const SBCDEC_FIXED_EXTRA_BITS: u8 = 2;
 
 #[derive(Debug)]
 pub enum FrameDecodeError {
@@ -22,13 +21,78 @@ impl From<ByteError> for FrameDecodeError {
     }
 }
 
+/// CRC over the header, the joint flags and the scale factors, packed as in the stream: the
+/// flags MSB first from subband 0, for 4 subbands as well as 8.
+pub(crate) fn frame_crc<const CHANNELS: usize, const SUBBANDS: usize>(
+    header: &SBCHeader,
+    joint: u8,
+    scale_factor: &[[u8; SUBBANDS]; CHANNELS],
+) -> u8 {
+    let mut crc = crc8(0x0F, &header.encode_array()[1..], 16);
+    if let ChannelMode::SBC_MODE_JOINT_STEREO = header.channel_mode() {
+        let mut b = 0;
+        for sb in 0..SUBBANDS {
+            if (joint & (1 << sb)) != 0 {
+                b |= 0x80 >> sb;
+            }
+        }
+        crc = crc8(crc, &[b], SUBBANDS);
+    }
+    for ch in 0..CHANNELS {
+        let mut b = [0_u8; SUBBANDS];
+        for sb in 0..SUBBANDS {
+            b[sb >> 1] |= (scale_factor[ch][sb] & 0x0F) << ((!sb & 1) * 4);
+        }
+        crc = crc8(crc, &b, SUBBANDS * 4);
+    }
+    crc
+}
+
+pub(crate) fn dequantize_sample(scale_factor: u8, bits: u8, quantized: u16) -> i32 {
+    if bits == 0 {
+        return 0;
+    }
+    let shift = scale_factor + 1 + SBCDEC_FIXED_EXTRA_BITS;
+    let s = quantized as i64;
+
+    ((((s << 1 | 1) << shift) / ((1 << bits) - 1)) - (1 << shift)) as i32
+    // (((s << 1 | 1) << (shift - bits)) - (1 << shift))
+}
+
+pub(crate) fn dequantize<const CHANNELS: usize, const SUBBANDS: usize>(
+    joint: u8,
+    scale_factor: &[[u8; SUBBANDS]; CHANNELS],
+    bits: &[[u8; SUBBANDS]; CHANNELS],
+    quantized: &[[u16; SUBBANDS]; CHANNELS],
+) -> [[i32; SUBBANDS]; CHANNELS] {
+    let mut sample = [[0_i32; SUBBANDS]; CHANNELS];
+    for ch in 0..CHANNELS {
+        for sb in 0..SUBBANDS {
+            sample[ch][sb] =
+                dequantize_sample(scale_factor[ch][sb], bits[ch][sb], quantized[ch][sb]);
+        }
+    }
+    if CHANNELS == 2 && joint != 0 {
+        for sb in 0..SUBBANDS {
+            if (joint & (1 << sb)) != 0 {
+                let l = sample[0][sb] + sample[1][sb];
+                let r = sample[0][sb] - sample[1][sb];
+                sample[0][sb] = l;
+                sample[1][sb] = r;
+            }
+        }
+    }
+    sample
+}
+
+/// Reads the side information of a frame and then its blocks as subband samples, without
+/// running any synthesis.
 #[derive(Debug)]
-pub struct FrameDecoder<'d, 'b, const CHANNELS: usize, const SUBBANDS: usize, B: ByteInput>
+pub struct FrameReader<'b, const CHANNELS: usize, const SUBBANDS: usize, B: ByteInput>
 where
     Channels<CHANNELS>: ValidChannels,
     Subbands<SUBBANDS>: ValidSubbands,
 {
-    decoder: &'d mut FilterState<CHANNELS, SUBBANDS>,
     joint: u8,
     scale_factor: [[u8; SUBBANDS]; CHANNELS],
     bits: [[u8; SUBBANDS]; CHANNELS],
@@ -36,24 +100,30 @@ where
     blocks: usize,
 }
 
-impl<'d, 'b, const CHANNELS: usize, const SUBBANDS: usize, B: ByteInput>
-    FrameDecoder<'d, 'b, CHANNELS, SUBBANDS, B>
+impl<'b, const CHANNELS: usize, const SUBBANDS: usize, B: ByteInput>
+    FrameReader<'b, CHANNELS, SUBBANDS, B>
 where
     Channels<CHANNELS>: ValidChannels,
     Subbands<SUBBANDS>: ValidSubbands,
 {
-    pub fn new_no_crc(
-        header: &SBCHeader,
-        decoder: &'d mut FilterState<CHANNELS, SUBBANDS>,
-        buffer: &'b mut B,
-    ) -> Result<Self, FrameDecodeError> {
-        if header.channel_mode().channels() != CHANNELS || header.subbands().subbands() != SUBBANDS
+    pub fn new_no_crc(header: &SBCHeader, buffer: &'b mut B) -> Result<Self, FrameDecodeError> {
+        if header.channel_mode().channels() != CHANNELS
+            || header.subbands().subbands() != SUBBANDS
+            || header.bitpool() > header.max_bitpool()
         {
             return Err(FrameDecodeError::SizeBed);
         }
         let mut i = BitInput::new(buffer);
         let joint = if let ChannelMode::SBC_MODE_JOINT_STEREO = header.channel_mode() {
-            i.read_u8(SUBBANDS)?
+            // The first flag in the stream belongs to subband 0.
+            let j = i.read_u8(SUBBANDS)?;
+            let mut joint = 0;
+            for sb in 0..SUBBANDS {
+                if (j & (1 << (SUBBANDS - 1 - sb))) != 0 {
+                    joint |= 1 << sb;
+                }
+            }
+            joint
         } else {
             0
         };
@@ -67,10 +137,9 @@ where
             s
         };
 
-        let bits = sbc::calculate_bits(header, &scale_factor);
+        let bits = sbc::calculate_bits(header, &scale_factor)?.bits;
 
         Ok(Self {
-            decoder,
             joint,
             scale_factor,
             bits,
@@ -79,97 +148,173 @@ where
         })
     }
 
-    fn crc(&self, header: &SBCHeader) -> u8 {
-        let mut crc = crc8(0x0F, &header.encode_array()[1..], 16);
-        if let ChannelMode::SBC_MODE_JOINT_STEREO = header.channel_mode() {
-            crc = crc8(
-                crc,
-                &[self.joint >> (8 - header.subbands().subbands())],
-                header.subbands().subbands(),
-            );
+    pub fn new(header: &SBCHeader, buffer: &'b mut B) -> Result<Self, FrameDecodeError> {
+        let crc = buffer.read_u8()?;
+        let s = Self::new_no_crc(header, buffer)?;
+        if frame_crc(header, s.joint, &s.scale_factor) == crc {
+            Ok(s)
+        } else {
+            Err(FrameDecodeError::CRCBed)
+        }
+    }
+
+    pub fn new_skip_crc(header: &SBCHeader, buffer: &'b mut B) -> Result<Self, FrameDecodeError> {
+        let _crc = buffer.read_u8()?;
+        Self::new_no_crc(header, buffer)
+    }
+
+    /// Bit `sb` is set when subband `sb` is mid/side coded.
+    pub fn joint(&self) -> u8 {
+        self.joint
+    }
+
+    pub fn scale_factor(&self) -> &[[u8; SUBBANDS]; CHANNELS] {
+        &self.scale_factor
+    }
+
+    pub fn bits(&self) -> &[[u8; SUBBANDS]; CHANNELS] {
+        &self.bits
+    }
+
+    /// Number of blocks not read yet.
+    pub fn blocks(&self) -> usize {
+        self.blocks
+    }
+
+    pub fn read_quantized(&mut self) -> Result<[[u16; SUBBANDS]; CHANNELS], FrameDecodeError> {
+        if self.blocks == 0 {
+            return Err(FrameDecodeError::NoBlock);
         }
+        self.blocks -= 1;
+        let mut q = [[0_u16; SUBBANDS]; CHANNELS];
         for ch in 0..CHANNELS {
-            let mut b = [0_u8; SUBBANDS];
             for sb in 0..SUBBANDS {
-                b[sb >> 1] |= (self.scale_factor[ch][sb] & 0x0F) << ((!sb & 1) * 4);
+                q[ch][sb] = self.buffer.read_u16(self.bits[ch][sb] as usize)?;
             }
-            crc = crc8(crc, &b, SUBBANDS * 4);
         }
-        crc
+        Ok(q)
+    }
+
+    pub fn dequantize(
+        &self,
+        quantized: &[[u16; SUBBANDS]; CHANNELS],
+    ) -> [[i32; SUBBANDS]; CHANNELS] {
+        dequantize(self.joint, &self.scale_factor, &self.bits, quantized)
+    }
+
+    pub fn read_block(&mut self) -> Result<[[i32; SUBBANDS]; CHANNELS], FrameDecodeError> {
+        let q = self.read_quantized()?;
+        Ok(self.dequantize(&q))
+    }
+}
+
+#[derive(Debug)]
+pub struct FrameDecoder<
+    'd,
+    'b,
+    const CHANNELS: usize,
+    const SUBBANDS: usize,
+    B: ByteInput,
+    F: SynthesisFilter<CHANNELS, SUBBANDS> = FilterState<CHANNELS, SUBBANDS>,
+> where
+    Channels<CHANNELS>: ValidChannels,
+    Subbands<SUBBANDS>: ValidSubbands,
+{
+    decoder: &'d mut F,
+    reader: FrameReader<'b, CHANNELS, SUBBANDS, B>,
+}
+
+impl<
+        'd,
+        'b,
+        const CHANNELS: usize,
+        const SUBBANDS: usize,
+        B: ByteInput,
+        F: SynthesisFilter<CHANNELS, SUBBANDS>,
+    > FrameDecoder<'d, 'b, CHANNELS, SUBBANDS, B, F>
+where
+    Channels<CHANNELS>: ValidChannels,
+    Subbands<SUBBANDS>: ValidSubbands,
+{
+    pub fn new_no_crc(
+        header: &SBCHeader,
+        decoder: &'d mut F,
+        buffer: &'b mut B,
+    ) -> Result<Self, FrameDecodeError> {
+        let reader = FrameReader::new_no_crc(header, buffer)?;
+        Ok(Self { decoder, reader })
     }
 
     pub fn new(
         header: &SBCHeader,
-        decoder: &'d mut FilterState<CHANNELS, SUBBANDS>,
+        decoder: &'d mut F,
         buffer: &'b mut B,
     ) -> Result<Self, FrameDecodeError> {
-        let crc = buffer.read_u8()?;
-        let s = Self::new_no_crc(header, decoder, buffer)?;
-        if s.crc(header) == crc {
-            Ok(s)
-        } else {
-            Err(FrameDecodeError::CRCBed)
-        }
+        let reader = FrameReader::new(header, buffer)?;
+        Ok(Self { decoder, reader })
     }
 
     pub fn new_skip_crc(
         header: &SBCHeader,
-        decoder: &'d mut FilterState<CHANNELS, SUBBANDS>,
+        decoder: &'d mut F,
         buffer: &'b mut B,
     ) -> Result<Self, FrameDecodeError> {
-        let _crc = buffer.read_u8()?;
-        Self::new_no_crc(header, decoder, buffer)
+        let reader = FrameReader::new_skip_crc(header, buffer)?;
+        Ok(Self { decoder, reader })
     }
 
-    pub fn next(&mut self) -> Result<[[i16; SUBBANDS]; CHANNELS], FrameDecodeError> {
-        if self.blocks == 0 {
+    pub fn reader(&self) -> &FrameReader<'b, CHANNELS, SUBBANDS, B> {
+        &self.reader
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<[[F::Sample; SUBBANDS]; CHANNELS], FrameDecodeError> {
+        let sample = self.reader.read_block()?;
+        Ok(self.decoder.filter(&sample))
+    }
+
+    /// Dequantizes all remaining blocks first, then synthesizes them in one pass.
+    /// Returns the number of blocks written to `o`.
+    pub fn decode_frame(
+        &mut self,
+        o: &mut [[[F::Sample; SUBBANDS]; CHANNELS]],
+    ) -> Result<usize, FrameDecodeError> {
+        let blocks = self.reader.blocks();
+        if blocks == 0 {
             return Err(FrameDecodeError::NoBlock);
         }
-        self.blocks -= 1;
-        let mut sample = [[0_i32; SUBBANDS]; CHANNELS];
-        for ch in 0..CHANNELS {
-            for sb in 0..SUBBANDS {
-                let bits = self.bits[ch][sb];
-                if bits == 0 {
-                    sample[ch][sb] = 0;
-                    continue;
-                }
-                let shift = self.scale_factor[ch][sb] + 1 + SBCDEC_FIXED_EXTRA_BITS;
-                let s = self.buffer.read_u16(bits as usize)? as i32;
-
-                sample[ch][sb] =
-                    ((((s as i32) << 1 | 1) << shift) / ((1 << bits) - 1)) - (1 << shift);
-                // sample[ch][sb] = (((s << 1 | 1) << (shift - bits)) - (1 << shift));
-            }
+        if o.len() < blocks {
+            return Err(FrameDecodeError::SizeBed);
         }
-        if CHANNELS == 2 && self.joint != 0 {
-            for sb in 0..SUBBANDS {
-                if (self.joint & (1 << sb)) != 0 {
-                    let l = sample[0][sb] + sample[1][sb];
-                    let r = sample[0][sb] - sample[1][sb];
-                    sample[0][sb] = l;
-                    sample[1][sb] = r;
-                }
-            }
+        let mut sample = [[[0_i32; SUBBANDS]; CHANNELS]; MAX_BLOCKS];
+        for b in 0..blocks {
+            sample[b] = self.reader.read_block()?;
         }
-
-        Ok(self.decoder.filter(&sample))
+        self.decoder
+            .filter_frame(&sample[..blocks], &mut o[..blocks]);
+        Ok(blocks)
     }
 }
 
-impl<'d, 'b, const CHANNELS: usize, const SUBBANDS: usize, B: ByteInput> Iterator
-    for FrameDecoder<'d, 'b, CHANNELS, SUBBANDS, B>
+impl<
+        'd,
+        'b,
+        const CHANNELS: usize,
+        const SUBBANDS: usize,
+        B: ByteInput,
+        F: SynthesisFilter<CHANNELS, SUBBANDS>,
+    > Iterator for FrameDecoder<'d, 'b, CHANNELS, SUBBANDS, B, F>
 where
     Channels<CHANNELS>: ValidChannels,
     Subbands<SUBBANDS>: ValidSubbands,
 {
-    type Item = [[i16; SUBBANDS]; CHANNELS];
+    type Item = [[F::Sample; SUBBANDS]; CHANNELS];
 
     fn next(&mut self) -> Option<Self::Item> {
         match self.next() {
             Ok(v) => Some(v),
             Err(FrameDecodeError::NoBlock) => None,
-            Err(e) => Err(e).unwrap(),
+            Err(e) => panic!("{:?}", e),
         }
     }
 }
@@ -0,0 +1,51 @@
+use crate::frame_decoder::{frame_crc, FrameDecodeError};
+use crate::header::{Blocks, ChannelMode, SBCHeader, MSBC_BLOCKS};
+use crate::io::{BitOutput, ByteOutput};
+
+/// Writes a complete frame, header and CRC included, from its side information and quantized
+/// samples. `joint` uses the same layout as [`FrameReader::joint`](crate::frame_decoder::FrameReader::joint),
+/// and `bits` must be the allocation of `header` for `scale_factor`. Nothing is written to an
+/// output with less room than [`SBCHeader::frame_length`].
+pub fn write_frame<const CHANNELS: usize, const SUBBANDS: usize, O: ByteOutput>(
+    header: &SBCHeader,
+    joint: u8,
+    scale_factor: &[[u8; SUBBANDS]; CHANNELS],
+    bits: &[[u8; SUBBANDS]; CHANNELS],
+    quantized: &[[[u16; SUBBANDS]; CHANNELS]],
+    output: &mut O,
+) -> Result<(), FrameDecodeError> {
+    if header.channel_mode().channels() != CHANNELS
+        || header.subbands().subbands() != SUBBANDS
+        || header.blocks().map(Blocks::blocks).unwrap_or(MSBC_BLOCKS) != quantized.len()
+    {
+        return Err(FrameDecodeError::SizeBed);
+    }
+    if output
+        .remaining()
+        .is_some_and(|n| n < header.frame_length())
+    {
+        return Err(FrameDecodeError::ByteError(()));
+    }
+    header.encode(output)?;
+    output.write_u8(frame_crc(header, joint, scale_factor))?;
+    let mut o = BitOutput::new(output);
+    if let ChannelMode::SBC_MODE_JOINT_STEREO = header.channel_mode() {
+        for sb in 0..SUBBANDS {
+            o.write_u8(1, (joint >> sb) & 1)?;
+        }
+    }
+    for ch in 0..CHANNELS {
+        for sb in 0..SUBBANDS {
+            o.write_u8(4, scale_factor[ch][sb])?;
+        }
+    }
+    for q in quantized {
+        for ch in 0..CHANNELS {
+            for sb in 0..SUBBANDS {
+                o.write_u16(bits[ch][sb] as usize, q[ch][sb])?;
+            }
+        }
+    }
+    o.flush()?;
+    Ok(())
+}
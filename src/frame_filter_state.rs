@@ -0,0 +1,163 @@
+use crate::filter_state::SynthesisFilter;
+use crate::header::MAX_BLOCKS;
+use crate::io::{ByteError, ByteInput, ByteOutput};
+use crate::sbc::FILTER_ORDER;
+use crate::sbc::{Channels, Subbands, ValidChannels, ValidSubbands};
+use core::fmt::{Debug, Formatter};
+
+const HISTORY: usize = FILTER_ORDER - 1;
+
+/// Synthesis filter that works on a whole frame at a time.
+///
+/// The matrixed history is kept in a linear buffer, oldest block first, so the windowing of
+/// each block reads `FILTER_ORDER` consecutive rows. The output is bit-exact with
+/// [`FilterState`](crate::filter_state::FilterState).
+#[derive(Copy, Clone)]
+pub struct FrameFilterState<const CHANNELS: usize, const SUBBANDS: usize>
+where
+    Channels<CHANNELS>: ValidChannels,
+    Subbands<SUBBANDS>: ValidSubbands,
+{
+    history: [[[i32; SUBBANDS]; HISTORY + MAX_BLOCKS]; CHANNELS],
+}
+
+impl<const CHANNELS: usize, const SUBBANDS: usize> FrameFilterState<CHANNELS, SUBBANDS>
+where
+    Channels<CHANNELS>: ValidChannels,
+    Subbands<SUBBANDS>: ValidSubbands,
+{
+    pub const fn new() -> Self {
+        Self {
+            history: [[[0; SUBBANDS]; HISTORY + MAX_BLOCKS]; CHANNELS],
+        }
+    }
+
+    /// Clears the history, as if nothing had been decoded yet.
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    /// Length in bytes of [`FrameFilterState::snapshot`].
+    pub const fn snapshot_len() -> usize {
+        2 + CHANNELS * HISTORY * SUBBANDS * 4
+    }
+
+    /// Writes the state as `CHANNELS` and `SUBBANDS` in one byte each, followed by the
+    /// matrixed blocks of the history as little-endian `i32`, channel by channel and oldest
+    /// block first. It is not interchangeable with a
+    /// [`FilterState::snapshot`](crate::filter_state::FilterState::snapshot).
+    pub fn snapshot<O: ByteOutput>(&self, output: &mut O) -> Result<(), ByteError> {
+        if output.remaining().is_some_and(|n| n < Self::snapshot_len()) {
+            return Err(());
+        }
+        output.write(&[CHANNELS as u8, SUBBANDS as u8])?;
+        for ch in 0..CHANNELS {
+            for row in &self.history[ch][..HISTORY] {
+                for v in row {
+                    output.write(&v.to_le_bytes())?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads a state written by [`FrameFilterState::snapshot`] for the same configuration.
+    pub fn restore<B: ByteInput>(input: &mut B) -> Result<Self, ByteError> {
+        let mut head = [0; 2];
+        input.read(&mut head)?;
+        if head[0] as usize != CHANNELS || head[1] as usize != SUBBANDS {
+            return Err(());
+        }
+        let mut state = Self::new();
+        for ch in 0..CHANNELS {
+            for row in state.history[ch][..HISTORY].iter_mut() {
+                for v in row.iter_mut() {
+                    let mut b = [0; 4];
+                    input.read(&mut b)?;
+                    *v = i32::from_le_bytes(b);
+                }
+            }
+        }
+        Ok(state)
+    }
+
+    /// Filters any number of blocks, [`MAX_BLOCKS`] at a time.
+    pub fn filter_frame(
+        &mut self,
+        s: &[[[i32; SUBBANDS]; CHANNELS]],
+        o: &mut [[[i16; SUBBANDS]; CHANNELS]],
+    ) {
+        assert!(o.len() >= s.len());
+        for (s, o) in s.chunks(MAX_BLOCKS).zip(o.chunks_mut(MAX_BLOCKS)) {
+            self.filter_blocks(s, o);
+        }
+    }
+
+    fn filter_blocks(
+        &mut self,
+        s: &[[[i32; SUBBANDS]; CHANNELS]],
+        o: &mut [[[i16; SUBBANDS]; CHANNELS]],
+    ) {
+        let blocks = s.len();
+        for ch in 0..CHANNELS {
+            let h = &mut self.history[ch];
+            for b in 0..blocks {
+                <Subbands<SUBBANDS> as ValidSubbands>::matrix16(&s[b][ch], &mut h[HISTORY + b]);
+            }
+            for b in 0..blocks {
+                <Subbands<SUBBANDS> as ValidSubbands>::window16(
+                    h[b..b + FILTER_ORDER].as_flattened(),
+                    &mut o[b][ch],
+                );
+            }
+            h.copy_within(blocks..blocks + HISTORY, 0);
+        }
+    }
+}
+
+impl<const CHANNELS: usize, const SUBBANDS: usize> SynthesisFilter<CHANNELS, SUBBANDS>
+    for FrameFilterState<CHANNELS, SUBBANDS>
+where
+    Channels<CHANNELS>: ValidChannels,
+    Subbands<SUBBANDS>: ValidSubbands,
+{
+    type Sample = i16;
+
+    fn filter(&mut self, s: &[[i32; SUBBANDS]; CHANNELS]) -> [[i16; SUBBANDS]; CHANNELS] {
+        let mut o = [[[0; SUBBANDS]; CHANNELS]; 1];
+        FrameFilterState::filter_frame(self, core::slice::from_ref(s), &mut o);
+        o[0]
+    }
+
+    fn filter_frame(
+        &mut self,
+        s: &[[[i32; SUBBANDS]; CHANNELS]],
+        o: &mut [[[i16; SUBBANDS]; CHANNELS]],
+    ) {
+        FrameFilterState::filter_frame(self, s, o)
+    }
+}
+
+impl<const CHANNELS: usize, const SUBBANDS: usize> Default for FrameFilterState<CHANNELS, SUBBANDS>
+where
+    Channels<CHANNELS>: ValidChannels,
+    Subbands<SUBBANDS>: ValidSubbands,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const CHANNELS: usize, const SUBBANDS: usize> Debug for FrameFilterState<CHANNELS, SUBBANDS>
+where
+    Channels<CHANNELS>: ValidChannels,
+    Subbands<SUBBANDS>: ValidSubbands,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        let mut s = f.debug_struct("FrameFilterState");
+        s.field("CHANNELS", &CHANNELS);
+        s.field("SUBBANDS", &SUBBANDS);
+        s.field("history", &self.history);
+        s.finish()
+    }
+}
@@ -0,0 +1,186 @@
+use crate::filter_state::SynthesisFilter;
+use crate::header::MAX_BLOCKS;
+
+/// Unity gain of [`SubbandGain::gain`].
+pub const GAIN_ONE: i32 = 1 << 16;
+/// Largest gain of [`db_to_gain`]. The gain stages only attenuate: the 32-bit synthesis
+/// filters have no headroom above a full-scale stream.
+pub const MAX_GAIN_DB: f32 = 0.0;
+/// Gains at or below this mute the channel.
+pub const MUTE_GAIN_DB: f32 = -100.0;
+
+/// `10 ^ (db / 20)` as a Q16 fixed-point value, clamped to [`MAX_GAIN_DB`].
+pub fn db_to_gain(db: f32) -> i32 {
+    db_to_gain_max(db, MAX_GAIN_DB)
+}
+
+/// `10 ^ (db / 20)` as a Q16 fixed-point value, clamped to `max_db`.
+pub(crate) fn db_to_gain_max(db: f32, max_db: f32) -> i32 {
+    if db <= MUTE_GAIN_DB {
+        return 0;
+    }
+    let db = if db > max_db { max_db } else { db };
+    // 10 ^ (db / 20) = 2 ^ (db * log2(10) / 20), split into 2 ^ n * 2 ^ f.
+    let x = db * 0.166_096_4;
+    let mut n = x as i32;
+    if n as f32 > x {
+        n -= 1;
+    }
+    let f = x - n as f32;
+    let p = 1.0
+        + f * (core::f32::consts::LN_2
+            + f * (0.240_226_5 + f * (0.055_504_1 + f * (0.009_618_1 + f * 0.001_333_6))));
+    let v = (p * GAIN_ONE as f32) as i64;
+    (if n >= 0 { v << n } else { v >> -n }) as i32
+}
+
+/// Scales `v` by the Q16 `gain`.
+pub(crate) fn apply_gain(v: i32, gain: i32) -> i32 {
+    let v = (v as i64 * gain as i64) >> 16;
+    v.clamp(i32::MIN as i64, i32::MAX as i64) as i32
+}
+
+/// A Q16 gain that moves linearly to its target, one step per block.
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct Ramp {
+    gain: i32,
+    target: i32,
+    step: i32,
+    blocks: u16,
+}
+
+impl Ramp {
+    pub(crate) const fn new(gain: i32) -> Self {
+        Self {
+            gain,
+            target: gain,
+            step: 0,
+            blocks: 0,
+        }
+    }
+
+    pub(crate) fn gain(&self) -> i32 {
+        self.gain
+    }
+
+    pub(crate) fn set(&mut self, gain: i32, blocks: u16) {
+        self.target = gain;
+        if blocks == 0 {
+            self.gain = gain;
+            self.step = 0;
+            self.blocks = 0;
+        } else {
+            self.step = (gain - self.gain) / blocks as i32;
+            self.blocks = blocks;
+        }
+    }
+
+    pub(crate) fn next(&mut self) -> i32 {
+        if self.blocks > 0 {
+            self.blocks -= 1;
+            self.gain = if self.blocks == 0 {
+                self.target
+            } else {
+                self.gain + self.step
+            };
+        }
+        self.gain
+    }
+}
+
+/// Applies a per-channel gain to the subband samples before they reach `F`.
+///
+/// The synthesis filter is linear, so this is the same as scaling the PCM output, but without
+/// first rounding and saturating to the output format. Gain changes are ramped linearly over
+/// a number of blocks.
+///
+/// The stage only attenuates. Gains are capped at unity, like those of [`db_to_gain`]: the
+/// 32-bit synthesis filters have no headroom above a full-scale stream, and a boost overflows
+/// their matrixing.
+#[derive(Debug)]
+pub struct SubbandGain<F, const CHANNELS: usize> {
+    filter: F,
+    ramp: [Ramp; CHANNELS],
+}
+
+impl<F, const CHANNELS: usize> SubbandGain<F, CHANNELS> {
+    pub const fn new(filter: F) -> Self {
+        Self {
+            filter,
+            ramp: [Ramp::new(GAIN_ONE); CHANNELS],
+        }
+    }
+
+    pub fn inner(&self) -> &F {
+        &self.filter
+    }
+
+    pub fn inner_mut(&mut self) -> &mut F {
+        &mut self.filter
+    }
+
+    pub fn into_inner(self) -> F {
+        self.filter
+    }
+
+    /// Current gain of channel `ch` in Q16.
+    pub fn gain(&self, ch: usize) -> i32 {
+        self.ramp[ch].gain()
+    }
+
+    /// Moves the gain of channel `ch` to `db`, at most [`MAX_GAIN_DB`], over `ramp_blocks`
+    /// blocks.
+    pub fn set_gain_db(&mut self, ch: usize, db: f32, ramp_blocks: u16) {
+        self.set_gain(ch, db_to_gain(db), ramp_blocks);
+    }
+
+    /// Moves the gain of channel `ch` to the Q16 `gain`, clamped to `0..=GAIN_ONE`, over
+    /// `ramp_blocks` blocks.
+    pub fn set_gain(&mut self, ch: usize, gain: i32, ramp_blocks: u16) {
+        self.ramp[ch].set(gain.clamp(0, GAIN_ONE), ramp_blocks);
+    }
+
+    fn apply<const SUBBANDS: usize>(
+        &mut self,
+        s: &[[i32; SUBBANDS]; CHANNELS],
+    ) -> [[i32; SUBBANDS]; CHANNELS] {
+        let mut o = *s;
+        for ch in 0..CHANNELS {
+            let gain = self.ramp[ch].next();
+            if gain == GAIN_ONE {
+                continue;
+            }
+            for sb in 0..SUBBANDS {
+                o[ch][sb] = apply_gain(s[ch][sb], gain);
+            }
+        }
+        o
+    }
+}
+
+impl<F, const CHANNELS: usize, const SUBBANDS: usize> SynthesisFilter<CHANNELS, SUBBANDS>
+    for SubbandGain<F, CHANNELS>
+where
+    F: SynthesisFilter<CHANNELS, SUBBANDS>,
+{
+    type Sample = F::Sample;
+
+    fn filter(&mut self, s: &[[i32; SUBBANDS]; CHANNELS]) -> [[F::Sample; SUBBANDS]; CHANNELS] {
+        let s = self.apply(s);
+        self.filter.filter(&s)
+    }
+
+    fn filter_frame(
+        &mut self,
+        s: &[[[i32; SUBBANDS]; CHANNELS]],
+        o: &mut [[[F::Sample; SUBBANDS]; CHANNELS]],
+    ) {
+        for (s, o) in s.chunks(MAX_BLOCKS).zip(o.chunks_mut(MAX_BLOCKS)) {
+            let mut t = [[[0; SUBBANDS]; CHANNELS]; MAX_BLOCKS];
+            for b in 0..s.len() {
+                t[b] = self.apply(&s[b]);
+            }
+            self.filter.filter_frame(&t[..s.len()], o);
+        }
+    }
+}
@@ -0,0 +1,109 @@
+use crate::filter_state::SynthesisFilter;
+
+/// Turns decoded blocks into interleaved PCM aligned with the encoder input: the first
+/// [`SynthesisFilter::delay`] samples of each channel are dropped, so that sample `n` of the
+/// output is sample `n` of the encoder input.
+///
+/// The end of the stream can be handled in two ways:
+///
+/// * The encoder appends [`Gapless::padding_blocks`] blocks of silence and the decoder stops at
+///   the original length, set with [`Gapless::set_length`]. This gives the exact tail.
+/// * [`Gapless::flush`] runs zero blocks through the filter to get the delayed tail out. The
+///   last [`SynthesisFilter::delay`] samples then miss the part of their subband content the
+///   encoder never sent, but no cooperation from the encoder is needed.
+#[derive(Debug, Copy, Clone)]
+pub struct Gapless<const CHANNELS: usize, const SUBBANDS: usize> {
+    skip: usize,
+    delay: usize,
+    left: Option<u64>,
+}
+
+impl<const CHANNELS: usize, const SUBBANDS: usize> Gapless<CHANNELS, SUBBANDS> {
+    pub fn new<F: SynthesisFilter<CHANNELS, SUBBANDS>>(filter: &F) -> Self {
+        Self::with_delay(filter.delay())
+    }
+
+    pub const fn with_delay(delay: usize) -> Self {
+        Self {
+            skip: delay,
+            delay,
+            left: None,
+        }
+    }
+
+    /// Blocks of silence an encoder appends so that the whole input comes out of the decoder.
+    pub const fn padding_blocks(delay: usize) -> usize {
+        delay.div_ceil(SUBBANDS)
+    }
+
+    /// Stops the output after `length` more samples per channel, or never with `None`.
+    pub fn set_length(&mut self, length: Option<u64>) {
+        self.left = length;
+    }
+
+    /// Samples per channel still to be dropped.
+    pub fn skip(&self) -> usize {
+        self.skip
+    }
+
+    fn push<S: Copy>(
+        &mut self,
+        block: &[[S; SUBBANDS]; CHANNELS],
+        o: &mut [[S; CHANNELS]],
+    ) -> usize {
+        let mut n = 0;
+        for i in 0..SUBBANDS {
+            if self.skip > 0 {
+                self.skip -= 1;
+                continue;
+            }
+            match &mut self.left {
+                Some(0) => break,
+                Some(left) => *left -= 1,
+                None => {}
+            }
+            for ch in 0..CHANNELS {
+                o[n][ch] = block[ch][i];
+            }
+            n += 1;
+        }
+        n
+    }
+
+    /// Interleaves `blocks` into `o`, dropping what is left of the initial delay and anything
+    /// past the length. Returns the number of samples per channel written; `o` needs room for
+    /// all of `blocks`.
+    pub fn trim<S: Copy>(
+        &mut self,
+        blocks: &[[[S; SUBBANDS]; CHANNELS]],
+        o: &mut [[S; CHANNELS]],
+    ) -> usize {
+        let mut n = 0;
+        for block in blocks {
+            n += self.push(block, &mut o[n..]);
+        }
+        n
+    }
+
+    /// Ends the stream: feeds zero blocks to `filter` and writes the delayed tail to `o`,
+    /// which needs room for [`SynthesisFilter::delay`] samples. Returns the number of samples
+    /// per channel written and starts over for a new stream.
+    pub fn flush<F: SynthesisFilter<CHANNELS, SUBBANDS>>(
+        &mut self,
+        filter: &mut F,
+        o: &mut [[F::Sample; CHANNELS]],
+    ) -> usize {
+        // The part of the delay not dropped yet came from a stream shorter than the delay,
+        // and is dropped from the tail instead.
+        let tail = (self.delay - self.skip) as u64;
+        self.left = Some(self.left.map_or(tail, |left| left.min(tail)));
+        let mut n = 0;
+        while self.skip > 0 || self.left != Some(0) {
+            let block = filter.filter(&[[0; SUBBANDS]; CHANNELS]);
+            n += self.push(&block, &mut o[n..]);
+        }
+        self.skip = self.delay;
+        self.left = None;
+        n
+    }
+}
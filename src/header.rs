@@ -4,9 +4,15 @@ pub const SBC_SYNCWORD: u8 = 0x9C;
 pub const MSBC_SYNCWORD: u8 = 0xAD;
 
 pub const MSBC_BLOCKS: usize = 15;
+/// Fixed bitpool of mSBC, as in the HFP specification. It gives the 57 byte frames that
+/// the bit allocation of every mSBC frame depends on.
+pub const MSBC_BITPOOL: u8 = 26;
+pub const MAX_BLOCKS: usize = 16;
+/// Longest frame: dual channel, 8 subbands, 16 blocks and bitpool 128.
+pub const MAX_FRAME_LENGTH: usize = 524;
 
 #[allow(non_camel_case_types)]
-#[derive(Default, Debug, Copy, Clone)]
+#[derive(Default, Debug, Copy, Clone, Eq, PartialEq)]
 pub enum Frequency {
     #[default]
     SBC_FREQ_16000 = 0,
@@ -41,7 +47,7 @@ impl Frequency {
 }
 
 #[allow(non_camel_case_types)]
-#[derive(Default, Debug, Copy, Clone)]
+#[derive(Default, Debug, Copy, Clone, Eq, PartialEq)]
 pub enum Blocks {
     #[default]
     SBC_BLK_4 = 0,
@@ -76,7 +82,7 @@ impl Blocks {
 }
 
 #[allow(non_camel_case_types)]
-#[derive(Default, Debug, Copy, Clone)]
+#[derive(Default, Debug, Copy, Clone, Eq, PartialEq)]
 pub enum ChannelMode {
     #[default]
     SBC_MODE_MONO = 0,
@@ -100,7 +106,7 @@ impl ChannelMode {
         (self as u8) << 2
     }
 
-    pub fn channels(self) -> usize {
+    pub const fn channels(self) -> usize {
         match self {
             Self::SBC_MODE_MONO => 1,
             Self::SBC_MODE_DUAL_CHANNEL => 2,
@@ -111,7 +117,7 @@ impl ChannelMode {
 }
 
 #[allow(non_camel_case_types)]
-#[derive(Default, Debug, Copy, Clone)]
+#[derive(Default, Debug, Copy, Clone, Eq, PartialEq)]
 pub enum AllocationMethod {
     #[default]
     SBC_AM_LOUDNESS = 0,
@@ -133,7 +139,7 @@ impl AllocationMethod {
 }
 
 #[allow(non_camel_case_types)]
-#[derive(Default, Debug, Copy, Clone)]
+#[derive(Default, Debug, Copy, Clone, Eq, PartialEq)]
 pub enum Subbands {
     #[default]
     SBC_SB_4 = 0,
@@ -159,9 +165,14 @@ impl Subbands {
             Subbands::SBC_SB_8 => 8,
         }
     }
+
+    /// See [`codec_delay`](crate::sbc::codec_delay).
+    pub const fn delay(self) -> usize {
+        crate::sbc::codec_delay(self.subbands())
+    }
 }
 
-#[derive(Default, Debug, Copy, Clone)]
+#[derive(Default, Debug, Copy, Clone, Eq, PartialEq)]
 pub enum SBCHeader {
     #[default]
     MSBC,
@@ -255,9 +266,105 @@ impl SBCHeader {
             } => allocation_method,
         }
     }
+    /// Largest bitpool the bit allocation can spend: 16 bits for every subband of every
+    /// channel that shares the bitpool.
+    pub const fn max_bitpool(&self) -> u8 {
+        let sb = self.subbands().subbands();
+        match self.channel_mode() {
+            ChannelMode::SBC_MODE_MONO | ChannelMode::SBC_MODE_DUAL_CHANNEL => (16 * sb) as u8,
+            ChannelMode::SBC_MODE_STEREO | ChannelMode::SBC_MODE_JOINT_STEREO => {
+                if sb == 8 {
+                    u8::MAX
+                } else {
+                    (32 * sb) as u8
+                }
+            }
+        }
+    }
+    /// Length in bytes of a frame with this header, header and CRC included.
+    pub const fn frame_length(&self) -> usize {
+        let channels = self.channel_mode().channels();
+        let subbands = self.subbands().subbands();
+        let blocks = match self.blocks() {
+            Some(blocks) => blocks.blocks(),
+            None => MSBC_BLOCKS,
+        };
+        let bitpool = self.bitpool() as usize;
+        let bits = match self.channel_mode() {
+            ChannelMode::SBC_MODE_MONO | ChannelMode::SBC_MODE_DUAL_CHANNEL => {
+                blocks * channels * bitpool
+            }
+            ChannelMode::SBC_MODE_STEREO => blocks * bitpool,
+            ChannelMode::SBC_MODE_JOINT_STEREO => subbands + blocks * bitpool,
+        };
+        4 + (4 * subbands * channels) / 8 + bits.div_ceil(8)
+    }
+
+    /// Number of PCM samples per channel a frame with this header decodes to.
+    pub const fn frame_samples(&self) -> usize {
+        let blocks = match self.blocks() {
+            Some(blocks) => blocks.blocks(),
+            None => MSBC_BLOCKS,
+        };
+        blocks * self.subbands().subbands()
+    }
+
+    /// Whether both headers have the same configuration apart from the bitpool, which a
+    /// source may change from frame to frame.
+    pub fn same_configuration(&self, other: &Self) -> bool {
+        self.with_bitpool(0) == other.with_bitpool(0)
+    }
+
+    /// The same configuration with another bitpool. mSBC has a fixed bitpool.
+    pub const fn with_bitpool(&self, bitpool: u8) -> Option<Self> {
+        match *self {
+            SBCHeader::MSBC => None,
+            SBCHeader::SBC {
+                frequency,
+                blocks,
+                channel_mode,
+                allocation_method,
+                subbands,
+                ..
+            } => Some(SBCHeader::SBC {
+                frequency,
+                blocks,
+                channel_mode,
+                allocation_method,
+                subbands,
+                bitpool,
+            }),
+        }
+    }
+
+    /// The same configuration with another allocation method. mSBC always uses loudness.
+    pub const fn with_allocation_method(
+        &self,
+        allocation_method: AllocationMethod,
+    ) -> Option<Self> {
+        match *self {
+            SBCHeader::MSBC => None,
+            SBCHeader::SBC {
+                frequency,
+                blocks,
+                channel_mode,
+                subbands,
+                bitpool,
+                ..
+            } => Some(SBCHeader::SBC {
+                frequency,
+                blocks,
+                channel_mode,
+                allocation_method,
+                subbands,
+                bitpool,
+            }),
+        }
+    }
+
     pub const fn bitpool(&self) -> u8 {
         match *self {
-            SBCHeader::MSBC => 24,
+            SBCHeader::MSBC => MSBC_BITPOOL,
             SBCHeader::SBC { bitpool, .. } => bitpool,
         }
     }
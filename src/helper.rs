@@ -1,4 +1,3 @@
-
 #[allow(dead_code)]
 pub(crate) const fn round64(x: i64, r: usize) -> i64 {
     if r == 0 {
@@ -33,7 +32,6 @@ pub(crate) const fn round32(x: i32, r: usize) -> i32 {
     }
 }
 
-#[macro_export(crate)]
 macro_rules! const_for {
     ($i:ident in ($f:expr, $t:expr) $b:block) => {{
         let mut $i = $f;
@@ -47,6 +45,8 @@ macro_rules! const_for {
     }};
 }
 
+pub(crate) use const_for;
+
 pub(crate) const fn saturating_i16(v: i32) -> i16 {
     let x = v as i16;
     if v == (x as i32) {
@@ -57,3 +57,15 @@ pub(crate) const fn saturating_i16(v: i32) -> i16 {
         i16::MIN
     }
 }
+
+pub(crate) const fn saturating_i24(v: i64) -> i32 {
+    const MAX: i64 = (1 << 23) - 1;
+    const MIN: i64 = -(1 << 23);
+    if v > MAX {
+        MAX as i32
+    } else if v < MIN {
+        MIN as i32
+    } else {
+        v as i32
+    }
+}
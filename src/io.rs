@@ -18,7 +18,7 @@ impl ByteInput for &[u8] {
             return Err(());
         }
         let amt = data.len();
-        let (a, b) = core::mem::replace(self, &[]).split_at(amt);
+        let (a, b) = core::mem::take(self).split_at(amt);
         data[..amt].copy_from_slice(a);
         *self = b;
         Ok(())
@@ -28,6 +28,11 @@ impl ByteInput for &[u8] {
 pub trait ByteOutput {
     fn write(&mut self, data: &[u8]) -> Result<(), ByteError>;
 
+    /// Bytes that can still be written, if the output knows.
+    fn remaining(&self) -> Option<usize> {
+        None
+    }
+
     fn write_u8(&mut self, data: u8) -> Result<(), ByteError> {
         let b = [data];
         self.write(&b)?;
@@ -38,11 +43,15 @@ pub trait ByteOutput {
 impl ByteOutput for &mut [u8] {
     fn write(&mut self, data: &[u8]) -> Result<(), ByteError> {
         let amt = data.len().min(self.len());
-        let (a, b) = core::mem::replace(self, &mut []).split_at_mut(amt);
+        let (a, b) = core::mem::take(self).split_at_mut(amt);
         a.copy_from_slice(&data[..amt]);
         *self = b;
         Ok(())
     }
+
+    fn remaining(&self) -> Option<usize> {
+        Some(self.len())
+    }
 }
 
 pub struct BitInput<'b, B: ByteInput> {
@@ -75,12 +84,12 @@ impl<'b, B: ByteInput> BitInput<'b, B> {
         let s = self.o + 8 - bits;
         if s >= 8 {
             self.o = s - 8;
-            Ok((self.b >> self.o) & !(0xFF << bits))
+            Ok((self.b >> self.o) & (0xFF >> (8 - bits)))
         } else {
             self.o = s;
             let b = self.b;
             self.b = self.i.read_u8()?;
-            Ok(((b << (8 - s)) | (self.b >> s)) & !(0xFF << bits))
+            Ok(((((b as u16) << (8 - s)) as u8) | (self.b >> s)) & (0xFF >> (8 - bits)))
         }
     }
 
@@ -94,22 +103,78 @@ impl<'b, B: ByteInput> BitInput<'b, B> {
         let s = self.o + 16 - bits;
         if s >= 16 {
             self.o = s - 16;
-            return Ok(((self.b as u16) >> self.o) & !(0xFF << bits));
+            Ok(((self.b as u16) >> self.o) & !(0xFF << bits))
         } else if s >= 8 {
             self.o = s - 8;
             let b = self.b;
             self.b = self.i.read_u8()?;
-            return Ok((((b as u16) << (16 - s)) | (self.b as u16 >> (s - 8))) & !(0xFF << bits));
+            Ok((((b as u16) << (16 - s)) | (self.b as u16 >> (s - 8))) & !(0xFF << bits))
         } else {
             self.o = s;
             let b = self.b;
             let mut d = [0; 2];
             self.i.read(&mut d)?;
             self.b = d[1];
-            return Ok(
-                (((b as u16) << (16 - s)) | ((d[0] as u16) << (8 - s)) | (d[1] as u16 >> s))
-                    & !(0xFFFF << bits),
-            );
+            // With `s == 0` all of `b` is shifted out, which needs more than 16 bits.
+            Ok(((((b as u32) << (16 - s)) as u16)
+                | ((d[0] as u16) << (8 - s))
+                | (d[1] as u16 >> s))
+                & (0xFFFF >> (16 - bits)))
         }
     }
 }
+
+/// Writes bit fields MSB first. The last byte is zero padded by [`BitOutput::flush`].
+pub struct BitOutput<'b, B: ByteOutput> {
+    o: &'b mut B,
+    b: u32,
+    n: usize,
+}
+
+impl<'b, B: ByteOutput> Debug for BitOutput<'b, B> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        let mut s = f.debug_struct("BitOutput");
+        s.field("b", &self.b);
+        s.field("n", &self.n);
+        s.finish()
+    }
+}
+
+impl<'b, B: ByteOutput> BitOutput<'b, B> {
+    pub fn new(o: &'b mut B) -> Self {
+        Self { o, b: 0, n: 0 }
+    }
+
+    pub fn write_u8(&mut self, bits: usize, v: u8) -> Result<(), ByteError> {
+        if bits > 8 {
+            return Err(());
+        }
+        self.write_u16(bits, v as u16)
+    }
+
+    pub fn write_u16(&mut self, bits: usize, v: u16) -> Result<(), ByteError> {
+        if bits > 16 {
+            return Err(());
+        }
+        if bits == 0 {
+            return Ok(());
+        }
+        self.b = (self.b << bits) | (v as u32 & (0xFFFF >> (16 - bits)));
+        self.n += bits;
+        while self.n >= 8 {
+            self.n -= 8;
+            self.o.write_u8((self.b >> self.n) as u8)?;
+        }
+        self.b &= (1 << self.n) - 1;
+        Ok(())
+    }
+
+    /// Pads the pending bits to a whole byte with zeros and writes it.
+    pub fn flush(&mut self) -> Result<(), ByteError> {
+        if self.n != 0 {
+            let pad = 8 - self.n;
+            self.write_u8(pad, 0)?;
+        }
+        Ok(())
+    }
+}
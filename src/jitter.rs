@@ -0,0 +1,337 @@
+//! A fixed-capacity jitter buffer for frames keyed on RTP timestamps or sequence numbers.
+
+/// Settings of a [`JitterBuffer`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct JitterConfig {
+    /// Key increment from one frame to the next: the samples per frame for RTP timestamps,
+    /// 1 for sequence numbers.
+    pub step: u32,
+    /// Bounds of the adaptive target latency, in frames.
+    pub min_target: usize,
+    pub max_target: usize,
+    /// Pops over which the buffer has to stay above its target before one frame is dropped
+    /// to bring the latency down.
+    pub window: u32,
+}
+
+/// What [`JitterBuffer::push`] did with a frame.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Push {
+    Accepted,
+    /// Its playout time has passed, it was discarded.
+    Late,
+    /// A frame with this key is already buffered.
+    Duplicate,
+    /// Too far ahead of the playout point, or larger than a slot.
+    Overflow,
+    /// The key is not a whole number of steps away from the stream.
+    Misaligned,
+}
+
+/// What [`JitterBuffer::pop`] hands to the decoder for the current frame period.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Pop {
+    /// Still filling up to the target latency at the start of the stream. Play silence.
+    Buffering,
+    /// A frame of this many bytes with this key was written to the output.
+    Frame { key: u32, len: usize },
+    /// The frame with this key was lost, later ones are buffered. Conceal it.
+    Missing { key: u32 },
+    /// The buffer ran dry. The target latency went up and the buffer refills before playing
+    /// again. Conceal until then.
+    Underflow,
+    /// The output holds fewer than the `needed` bytes of the next frame. Nothing was
+    /// released, pop again with a larger one.
+    TooSmall { needed: usize },
+}
+
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct JitterStats {
+    pub accepted: u32,
+    pub late: u32,
+    pub duplicate: u32,
+    /// Frames rejected as [`Push::Overflow`] or [`Push::Misaligned`].
+    pub overflow: u32,
+    pub missing: u32,
+    pub underflow: u32,
+    /// Frames dropped to lower the latency.
+    pub dropped: u32,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Slot<const BYTES: usize> {
+    used: bool,
+    len: usize,
+    data: [u8; BYTES],
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum State {
+    Idle,
+    Buffering,
+    Playing,
+    Refilling,
+}
+
+/// Reorders frames into a ring of `SLOTS` slots of up to `BYTES` bytes and releases one per
+/// [`JitterBuffer::pop`], which the caller runs at the steady frame rate of the output.
+///
+/// The target latency starts at [`JitterConfig::min_target`] frames. Every underflow raises it
+/// by one frame; a buffer that stayed above its target for a whole [`JitterConfig::window`]
+/// lowers it by one and drops its oldest frame.
+///
+/// When the keys jump, e.g. after a dropout longer than the buffer or a restarted sender, the
+/// buffer follows them: it restarts at the key of a push that does not fit an empty buffer
+/// while refilling, or at the last of `SLOTS` pushes in a row that were rejected.
+#[derive(Debug, Clone)]
+pub struct JitterBuffer<const SLOTS: usize, const BYTES: usize> {
+    config: JitterConfig,
+    slots: [Slot<BYTES>; SLOTS],
+    head: usize,
+    next: u32,
+    last: u32,
+    state: State,
+    target: usize,
+    min_depth: usize,
+    pops: u32,
+    /// Pushes rejected in a row as late, too far ahead or misaligned.
+    rejected: usize,
+    stats: JitterStats,
+}
+
+impl<const SLOTS: usize, const BYTES: usize> JitterBuffer<SLOTS, BYTES> {
+    pub const fn new(config: JitterConfig) -> Self {
+        assert!(config.step > 0);
+        assert!(config.min_target <= config.max_target && config.max_target < SLOTS);
+        Self {
+            config,
+            slots: [Slot {
+                used: false,
+                len: 0,
+                data: [0; BYTES],
+            }; SLOTS],
+            head: 0,
+            next: 0,
+            last: 0,
+            state: State::Idle,
+            target: config.min_target,
+            min_depth: usize::MAX,
+            pops: 0,
+            rejected: 0,
+            stats: JitterStats {
+                accepted: 0,
+                late: 0,
+                duplicate: 0,
+                overflow: 0,
+                missing: 0,
+                underflow: 0,
+                dropped: 0,
+            },
+        }
+    }
+
+    /// Current target latency in frames.
+    pub fn target(&self) -> usize {
+        self.target
+    }
+
+    pub fn stats(&self) -> &JitterStats {
+        &self.stats
+    }
+
+    /// Frames from the playout point up to the newest buffered one, holes included.
+    pub fn depth(&self) -> usize {
+        if self.state == State::Idle {
+            return 0;
+        }
+        match self.offset(self.last) {
+            Some(n) if n >= 0 => n as usize + 1,
+            _ => 0,
+        }
+    }
+
+    /// Frames from the playout point to `key`, or `None` if it is not on the frame grid.
+    fn offset(&self, key: u32) -> Option<i64> {
+        let diff = key.wrapping_sub(self.next) as i32 as i64;
+        if diff % self.config.step as i64 != 0 {
+            return None;
+        }
+        Some(diff / self.config.step as i64)
+    }
+
+    fn slot(&mut self, offset: usize) -> &mut Slot<BYTES> {
+        &mut self.slots[(self.head + offset) % SLOTS]
+    }
+
+    /// Forgets every frame and starts over with the next push.
+    pub fn clear(&mut self) {
+        for slot in self.slots.iter_mut() {
+            slot.used = false;
+        }
+        self.state = State::Idle;
+        self.target = self.config.min_target;
+        self.min_depth = usize::MAX;
+        self.pops = 0;
+        self.rejected = 0;
+    }
+
+    /// Drops every buffered frame and moves the playout point to `key`.
+    fn resync(&mut self, key: u32) {
+        for slot in self.slots.iter_mut() {
+            slot.used = false;
+        }
+        self.head = 0;
+        self.next = key;
+        self.last = key;
+        self.min_depth = usize::MAX;
+        self.pops = 0;
+        self.rejected = 0;
+    }
+
+    pub fn push(&mut self, key: u32, frame: &[u8]) -> Push {
+        let r = self.insert(key, frame);
+        match r {
+            Push::Accepted => self.stats.accepted += 1,
+            Push::Late => self.stats.late += 1,
+            Push::Duplicate => self.stats.duplicate += 1,
+            Push::Overflow | Push::Misaligned => self.stats.overflow += 1,
+        }
+        r
+    }
+
+    fn insert(&mut self, key: u32, frame: &[u8]) -> Push {
+        if frame.len() > BYTES {
+            return Push::Overflow;
+        }
+        match self.state {
+            State::Idle => {
+                self.state = State::Buffering;
+                self.resync(key);
+            }
+            // Nothing left to keep, so a key out of reach starts the stream over.
+            State::Refilling
+                if self.depth() == 0
+                    && self.offset(key).is_none_or(|offset| offset >= SLOTS as i64) =>
+            {
+                self.resync(key);
+            }
+            _ => {}
+        }
+        let r = self.place(key, frame);
+        match r {
+            Push::Late | Push::Overflow | Push::Misaligned => {
+                self.rejected += 1;
+                if self.rejected >= SLOTS {
+                    self.resync(key);
+                    if self.state == State::Playing {
+                        self.state = State::Refilling;
+                    }
+                    return self.place(key, frame);
+                }
+            }
+            Push::Accepted | Push::Duplicate => self.rejected = 0,
+        }
+        r
+    }
+
+    fn place(&mut self, key: u32, frame: &[u8]) -> Push {
+        let offset = match self.offset(key) {
+            Some(offset) => offset,
+            None => return Push::Misaligned,
+        };
+        if offset < 0 {
+            // Before playback starts an early frame that came in late still fits in front.
+            let back = offset.unsigned_abs() as usize;
+            if self.state != State::Buffering || self.depth() + back > SLOTS {
+                return Push::Late;
+            }
+            self.head = (self.head + SLOTS - back) % SLOTS;
+            self.next = key;
+        } else if offset as usize >= SLOTS {
+            return Push::Overflow;
+        }
+        let offset = self.offset(key).unwrap_or(0) as usize;
+        let slot = self.slot(offset);
+        if slot.used {
+            return Push::Duplicate;
+        }
+        slot.used = true;
+        slot.len = frame.len();
+        slot.data[..frame.len()].copy_from_slice(frame);
+        if self
+            .offset(self.last)
+            .is_none_or(|last| offset as i64 > last)
+        {
+            self.last = key;
+        }
+        Push::Accepted
+    }
+
+    fn advance(&mut self) {
+        self.slots[self.head].used = false;
+        self.head = (self.head + 1) % SLOTS;
+        self.next = self.next.wrapping_add(self.config.step);
+    }
+
+    /// Releases the frame for the current frame period into `o`, which has to hold it whole.
+    pub fn pop(&mut self, o: &mut [u8]) -> Pop {
+        match self.state {
+            State::Idle => return Pop::Buffering,
+            State::Buffering | State::Refilling => {
+                if self.depth() < self.target.max(1) {
+                    return if self.state == State::Buffering {
+                        Pop::Buffering
+                    } else {
+                        Pop::Underflow
+                    };
+                }
+                self.state = State::Playing;
+            }
+            State::Playing => {}
+        }
+
+        let depth = self.depth();
+        if depth == 0 {
+            self.stats.underflow += 1;
+            self.target = (self.target + 1).min(self.config.max_target);
+            self.state = State::Refilling;
+            self.min_depth = usize::MAX;
+            self.pops = 0;
+            return Pop::Underflow;
+        }
+
+        let min_depth = self.min_depth.min(depth);
+        let window_end = self.pops + 1 >= self.config.window;
+        let drop = window_end && min_depth > self.target + 1 && depth > 1;
+        let slot = &self.slots[(self.head + drop as usize) % SLOTS];
+        if slot.used && slot.len > o.len() {
+            return Pop::TooSmall { needed: slot.len };
+        }
+
+        self.min_depth = min_depth;
+        self.pops += 1;
+        if window_end {
+            if drop {
+                self.stats.dropped += 1;
+                self.advance();
+                if self.target > self.config.min_target {
+                    self.target -= 1;
+                }
+            }
+            self.min_depth = usize::MAX;
+            self.pops = 0;
+        }
+
+        let key = self.next;
+        let slot = &self.slots[self.head];
+        let r = if slot.used {
+            o[..slot.len].copy_from_slice(&slot.data[..slot.len]);
+            Pop::Frame { key, len: slot.len }
+        } else {
+            self.stats.missing += 1;
+            Pop::Missing { key }
+        };
+        self.advance();
+        r
+    }
+}
@@ -1,11 +1,41 @@
 #![cfg_attr(not(test), no_std)]
+#![allow(clippy::needless_range_loop, clippy::result_unit_err)]
 
+#[cfg(feature = "futures")]
+extern crate std;
+
+pub mod analysis;
+#[cfg(feature = "futures")]
+pub mod async_io;
 pub mod crc;
+pub mod downmix;
+pub mod drift;
+pub mod dual_channel;
+pub mod edit;
+pub mod encoder;
+pub mod equalizer;
 pub mod filter_state;
+pub mod frame;
 pub mod frame_decoder;
+pub mod frame_encoder;
+pub mod frame_filter_state;
+pub mod gain;
+pub mod gapless;
 pub mod header;
 pub mod helper;
 pub mod io;
+pub mod jitter;
+pub mod meter;
+pub mod rate_control;
+pub mod resample;
 pub mod sbc;
+mod simd;
+pub mod simd_filter_state;
 pub mod table;
-pub mod test;
+#[cfg(test)]
+mod test;
+pub mod timeline;
+pub mod transcode;
+pub mod wide_filter_state;
+
+pub(crate) use helper::const_for;
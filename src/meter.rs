@@ -0,0 +1,128 @@
+//! Per-subband level metering, from the dequantized samples or straight from the scale factors.
+
+use crate::frame_decoder::SBCDEC_FIXED_EXTRA_BITS;
+
+/// Level of a full-scale 16-bit signal in subband sample units, the 0 dB reference of
+/// [`level_db`].
+pub const FULL_SCALE: u32 = 1 << (15 + SBCDEC_FIXED_EXTRA_BITS);
+/// What [`level_db`] reports for silence.
+pub const SILENCE_DB: f32 = -120.0;
+
+/// Peak and RMS magnitude of every subband over some blocks, in subband sample units.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct SubbandLevels<const CHANNELS: usize, const SUBBANDS: usize> {
+    pub peak: [[u32; SUBBANDS]; CHANNELS],
+    pub rms: [[u32; SUBBANDS]; CHANNELS],
+}
+
+impl<const CHANNELS: usize, const SUBBANDS: usize> Default for SubbandLevels<CHANNELS, SUBBANDS> {
+    fn default() -> Self {
+        Self {
+            peak: [[0; SUBBANDS]; CHANNELS],
+            rms: [[0; SUBBANDS]; CHANNELS],
+        }
+    }
+}
+
+impl<const CHANNELS: usize, const SUBBANDS: usize> SubbandLevels<CHANNELS, SUBBANDS> {
+    /// Measures dequantized subband samples, such as [`Frame::samples`](crate::frame::Frame::samples).
+    pub fn from_samples(samples: &[[[i32; SUBBANDS]; CHANNELS]]) -> Self {
+        let mut levels = Self::default();
+        if samples.is_empty() {
+            return levels;
+        }
+        for ch in 0..CHANNELS {
+            for sb in 0..SUBBANDS {
+                let mut peak = 0;
+                let mut energy = 0_u64;
+                for b in samples {
+                    let v = b[ch][sb].unsigned_abs();
+                    peak = peak.max(v);
+                    energy += v as u64 * v as u64;
+                }
+                levels.peak[ch][sb] = peak;
+                levels.rms[ch][sb] = (energy / samples.len() as u64).isqrt() as u32;
+            }
+        }
+        levels
+    }
+
+    /// Estimates the levels from the scale factors alone, without touching the samples.
+    ///
+    /// A scale factor only bounds the magnitude of its subband, so the peak is an upper bound
+    /// within 6 dB of the real one and the RMS assumes a sine. For mid/side coded subbands the
+    /// estimate is of the mid and side signals rather than left and right.
+    pub fn from_scale_factors(scale_factor: &[[u8; SUBBANDS]; CHANNELS]) -> Self {
+        let mut levels = Self::default();
+        for ch in 0..CHANNELS {
+            for sb in 0..SUBBANDS {
+                let peak = 1_u32 << (scale_factor[ch][sb] + 1 + SBCDEC_FIXED_EXTRA_BITS);
+                levels.peak[ch][sb] = peak;
+                // peak / sqrt(2)
+                levels.rms[ch][sb] = ((peak as u64 * 46341) >> 16) as u32;
+            }
+        }
+        levels
+    }
+}
+
+/// `20 * log10(level / FULL_SCALE)`, or [`SILENCE_DB`] for zero.
+pub fn level_db(level: u32) -> f32 {
+    if level == 0 {
+        return SILENCE_DB;
+    }
+    // log2(level) = e + log2(m) with m in [1, 2), ln(m) = 2 * atanh((m - 1) / (m + 1)).
+    let e = 31 - level.leading_zeros() as i32;
+    let m = level as f32 / (1_u64 << e) as f32;
+    let z = (m - 1.0) / (m + 1.0);
+    let z2 = z * z;
+    let ln_m = 2.0 * z * (1.0 + z2 * (1.0 / 3.0 + z2 * (1.0 / 5.0 + z2 * (1.0 / 7.0))));
+    let log2 =
+        (e - (15 + SBCDEC_FIXED_EXTRA_BITS as i32)) as f32 + ln_m * core::f32::consts::LOG2_E;
+    // 20 * log10(2)
+    (log2 * 6.020_6).max(SILENCE_DB)
+}
+
+/// A peak-hold meter: follows rising levels at once and lets them fall by a fixed factor per
+/// update, like the needle of a PPM.
+#[derive(Debug, Copy, Clone)]
+pub struct DecayingPeak<const CHANNELS: usize, const SUBBANDS: usize> {
+    level: [[u32; SUBBANDS]; CHANNELS],
+    decay: u32,
+}
+
+impl<const CHANNELS: usize, const SUBBANDS: usize> DecayingPeak<CHANNELS, SUBBANDS> {
+    /// `decay` is the Q16 factor applied to the held level on every update that does not
+    /// exceed it, e.g. `db_to_gain(-1.0)` for 1 dB per frame.
+    pub const fn new(decay: i32) -> Self {
+        Self {
+            level: [[0; SUBBANDS]; CHANNELS],
+            decay: if decay < 0 {
+                0
+            } else if decay > 0xFFFF {
+                0xFFFF
+            } else {
+                decay as u32
+            },
+        }
+    }
+
+    pub fn level(&self) -> &[[u32; SUBBANDS]; CHANNELS] {
+        &self.level
+    }
+
+    pub fn reset(&mut self) {
+        self.level = [[0; SUBBANDS]; CHANNELS];
+    }
+
+    /// Feeds new levels, typically [`SubbandLevels::peak`], and returns the held ones.
+    pub fn update(&mut self, level: &[[u32; SUBBANDS]; CHANNELS]) -> &[[u32; SUBBANDS]; CHANNELS] {
+        for ch in 0..CHANNELS {
+            for sb in 0..SUBBANDS {
+                let held = ((self.level[ch][sb] as u64 * self.decay as u64) >> 16) as u32;
+                self.level[ch][sb] = held.max(level[ch][sb]);
+            }
+        }
+        &self.level
+    }
+}
@@ -0,0 +1,124 @@
+//! Choosing the bitpool of an encoder: the largest one that fits a bitrate, a frame length or
+//! an A2DP packet, and adapting it at runtime to the quality of the link.
+
+use crate::header::SBCHeader;
+
+/// Smallest bitpool A2DP allows.
+pub const MIN_BITPOOL: u8 = 2;
+/// Largest bitpool A2DP allows, below the 255 that stereo with 8 subbands could spend.
+pub const A2DP_MAX_BITPOOL: u8 = 250;
+/// Bytes in front of the frames of an A2DP media packet: the RTP header and the one byte SBC
+/// payload header.
+pub const A2DP_PACKET_OVERHEAD: usize = 12 + 1;
+/// Frames an A2DP media packet can carry, limited by the four bit count of the payload header.
+pub const A2DP_MAX_FRAMES: usize = 15;
+
+/// Bitrate of the stream in bits per second, from the frame length and sampling frequency.
+pub fn bitrate(header: &SBCHeader) -> u32 {
+    let frequency = header.frequency().frequency() as u64;
+    (8 * header.frame_length() as u64 * frequency / header.frame_samples() as u64) as u32
+}
+
+/// Largest bitpool whose frames are at most `length` bytes, or `None` if not even the
+/// smallest one fits. Capped at [`A2DP_MAX_BITPOOL`]; mSBC has its bitpool fixed.
+pub fn bitpool_for_frame_length(header: &SBCHeader, length: usize) -> Option<u8> {
+    if let SBCHeader::MSBC = header {
+        return (header.frame_length() <= length).then_some(header.bitpool());
+    }
+    let max = header.max_bitpool().min(A2DP_MAX_BITPOOL);
+    (MIN_BITPOOL..=max).rev().find(|&bitpool| {
+        header
+            .with_bitpool(bitpool)
+            .is_some_and(|h| h.frame_length() <= length)
+    })
+}
+
+/// Largest bitpool whose stream stays at or below `bitrate` bits per second.
+pub fn bitpool_for_bitrate(header: &SBCHeader, bitrate: u32) -> Option<u8> {
+    let frequency = header.frequency().frequency() as u64;
+    let length = bitrate as u64 * header.frame_samples() as u64 / (8 * frequency);
+    bitpool_for_frame_length(header, length as usize)
+}
+
+/// Largest bitpool that fits `frames` frames into one A2DP media packet of `mtu` bytes.
+pub fn bitpool_for_mtu(header: &SBCHeader, mtu: usize, frames: usize) -> Option<u8> {
+    if frames == 0 || frames > A2DP_MAX_FRAMES {
+        return None;
+    }
+    let length = mtu.checked_sub(A2DP_PACKET_OVERHEAD)? / frames;
+    bitpool_for_frame_length(header, length)
+}
+
+/// Frames of `header` that fit into one A2DP media packet of `mtu` bytes.
+pub fn frames_per_packet(header: &SBCHeader, mtu: usize) -> usize {
+    let room = mtu.saturating_sub(A2DP_PACKET_OVERHEAD);
+    (room / header.frame_length()).min(A2DP_MAX_FRAMES)
+}
+
+/// How the link is doing, as judged by the transport: a growing transmit queue, failed
+/// flushes or a dropping RSSI.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum LinkQuality {
+    Good,
+    /// Holds the bitpool where it is.
+    Fair,
+    Poor,
+}
+
+/// Steers the bitpool between two bounds from link quality reports: a poor link cuts it by a
+/// quarter at once, and a run of good reports raises it again step by step.
+#[derive(Debug, Copy, Clone)]
+pub struct AdaptiveBitpool {
+    min: u8,
+    max: u8,
+    bitpool: u8,
+    recovery: u32,
+    step: u8,
+    good: u32,
+}
+
+impl AdaptiveBitpool {
+    /// Starts at `max`. By default 20 good reports in a row raise the bitpool by 2.
+    pub const fn new(min: u8, max: u8) -> Self {
+        assert!(MIN_BITPOOL <= min && min <= max);
+        Self {
+            min,
+            max,
+            bitpool: max,
+            recovery: 20,
+            step: 2,
+            good: 0,
+        }
+    }
+
+    pub const fn with_recovery(self, reports: u32, step: u8) -> Self {
+        Self {
+            recovery: reports,
+            step,
+            ..self
+        }
+    }
+
+    pub fn bitpool(&self) -> u8 {
+        self.bitpool
+    }
+
+    /// Takes one report and returns the bitpool to encode with from now on.
+    pub fn update(&mut self, quality: LinkQuality) -> u8 {
+        match quality {
+            LinkQuality::Good => {
+                self.good += 1;
+                if self.good >= self.recovery {
+                    self.good = 0;
+                    self.bitpool = self.bitpool.saturating_add(self.step).min(self.max);
+                }
+            }
+            LinkQuality::Fair => self.good = 0,
+            LinkQuality::Poor => {
+                self.good = 0;
+                self.bitpool = (self.bitpool - self.bitpool / 4).max(self.min);
+            }
+        }
+        self.bitpool
+    }
+}
@@ -0,0 +1,304 @@
+//! Sample-rate conversion of decoded PCM to one fixed output rate, whatever the sampling
+//! frequency of the stream.
+
+use crate::drift::interpolate;
+use crate::filter_state::FilterState;
+use crate::frame_decoder::{FrameDecodeError, FrameDecoder};
+use crate::header::{SBCHeader, MAX_BLOCKS};
+use crate::io::ByteInput;
+use crate::sbc::{Channels, Subbands, ValidChannels, ValidSubbands};
+use crate::table::{SINC_RESOLUTION, SINC_TABLE, SINC_ZERO_CROSSINGS};
+
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub enum Quality {
+    /// Linear interpolation, without anti-aliasing.
+    Fast,
+    /// Catmull-Rom cubic interpolation, without anti-aliasing.
+    #[default]
+    Medium,
+    /// Kaiser windowed sinc over 16 input samples, band-limited to the lower of the two
+    /// rates. Down to a sixth of the input rate, e.g. from 48 kHz to 8 kHz.
+    High,
+}
+
+/// Input samples kept per channel, enough for the sinc kernel at a sixth of the input rate.
+const HISTORY: usize = 2 * SINC_ZERO_CROSSINGS * 6;
+
+/// A rate of zero, or a conversion [`Quality::High`] cannot do.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct UnsupportedRate;
+
+/// Converts interleaved PCM from one rate to another by an exact rational ratio.
+#[derive(Debug, Copy, Clone)]
+pub struct RateConverter<const CHANNELS: usize> {
+    quality: Quality,
+    input: u32,
+    output: u32,
+    /// Input samples on either side of the interpolation point, 0 when passing through.
+    half: usize,
+    history: [[i16; HISTORY]; CHANNELS],
+    /// Position of the next output past the middle of the history, in `1 / output` input
+    /// samples.
+    phase: u64,
+}
+
+impl<const CHANNELS: usize> RateConverter<CHANNELS> {
+    /// Fails on a zero rate, or with [`Quality::High`] on an output below a sixth of the
+    /// input.
+    pub const fn new(quality: Quality, input: u32, output: u32) -> Result<Self, UnsupportedRate> {
+        let half = match Self::half(quality, input, output) {
+            Ok(half) => half,
+            Err(e) => return Err(e),
+        };
+        Ok(Self {
+            quality,
+            input,
+            output,
+            half,
+            history: [[0; HISTORY]; CHANNELS],
+            phase: 0,
+        })
+    }
+
+    const fn half(quality: Quality, input: u32, output: u32) -> Result<usize, UnsupportedRate> {
+        if input == 0 || output == 0 {
+            return Err(UnsupportedRate);
+        }
+        if input == output {
+            return Ok(0);
+        }
+        match quality {
+            Quality::Fast => Ok(1),
+            Quality::Medium => Ok(2),
+            Quality::High if input > output => {
+                let half =
+                    (SINC_ZERO_CROSSINGS as u64 * input as u64).div_ceil(output as u64) as usize;
+                if 2 * half > HISTORY {
+                    return Err(UnsupportedRate);
+                }
+                Ok(half)
+            }
+            Quality::High => Ok(SINC_ZERO_CROSSINGS),
+        }
+    }
+
+    pub fn quality(&self) -> Quality {
+        self.quality
+    }
+
+    pub fn input_rate(&self) -> u32 {
+        self.input
+    }
+
+    pub fn output_rate(&self) -> u32 {
+        self.output
+    }
+
+    /// Delay through the converter, in input samples.
+    pub fn latency(&self) -> usize {
+        self.half
+    }
+
+    pub fn reset(&mut self) {
+        self.history = [[0; HISTORY]; CHANNELS];
+        self.phase = 0;
+    }
+
+    /// Switches to a new input rate, starting over if it differs from the current one. An
+    /// unsupported rate leaves the converter as it was.
+    pub fn set_input_rate(&mut self, input: u32) -> Result<(), UnsupportedRate> {
+        if input != self.input {
+            self.half = Self::half(self.quality, input, self.output)?;
+            self.input = input;
+            self.reset();
+        }
+        Ok(())
+    }
+
+    /// Output samples per channel that `input` samples can produce at most, the room
+    /// [`RateConverter::process`] needs.
+    pub fn max_output(&self, input: usize) -> usize {
+        (input as u64 * self.output as u64).div_ceil(self.input as u64) as usize + 1
+    }
+
+    fn sinc(&self, history: &[i16], mu: i64) -> i16 {
+        let down = self.input > self.output;
+        let mut acc = 0_i64;
+        for (i, &x) in history.iter().enumerate() {
+            let distance = (((i as i64 + 1 - self.half as i64) << 16) - mu).unsigned_abs();
+            let mut position = distance * SINC_RESOLUTION as u64;
+            if down {
+                position = position * self.output as u64 / self.input as u64;
+            }
+            let index = (position >> 16) as usize;
+            if index >= SINC_ZERO_CROSSINGS * SINC_RESOLUTION {
+                continue;
+            }
+            let frac = (position & 0xFFFF) as i64;
+            let a = SINC_TABLE[index] as i64;
+            let b = SINC_TABLE[index + 1] as i64;
+            acc += x as i64 * (a + (((b - a) * frac) >> 16));
+        }
+        if down {
+            acc = acc * self.output as i64 / self.input as i64;
+        }
+        ((acc + (1 << 14)) >> 15).clamp(i16::MIN as i64, i16::MAX as i64) as i16
+    }
+
+    fn interpolate(&self, history: &[i16], mu: i64) -> i16 {
+        match self.quality {
+            Quality::Fast => {
+                let (a, b) = (history[0] as i64, history[1] as i64);
+                (a + (((b - a) * mu + (1 << 15)) >> 16)) as i16
+            }
+            Quality::Medium => interpolate(&[history[0], history[1], history[2], history[3]], mu),
+            Quality::High => self.sinc(history, mu),
+        }
+    }
+
+    /// Converts `input` into `o` and returns the number of samples per channel written.
+    pub fn process(&mut self, input: &[[i16; CHANNELS]], o: &mut [[i16; CHANNELS]]) -> usize {
+        if self.half == 0 {
+            o[..input.len()].copy_from_slice(input);
+            return input.len();
+        }
+        let len = 2 * self.half;
+        let (step, one) = (self.input as u64, self.output as u64);
+        let mut n = 0;
+        for sample in input {
+            for ch in 0..CHANNELS {
+                self.history[ch].copy_within(1..len, 0);
+                self.history[ch][len - 1] = sample[ch];
+            }
+            while self.phase < one {
+                let mu = ((self.phase << 16) / one) as i64;
+                for ch in 0..CHANNELS {
+                    o[n][ch] = self.interpolate(&self.history[ch][..len], mu);
+                }
+                n += 1;
+                self.phase += step;
+            }
+            self.phase -= one;
+        }
+        n
+    }
+}
+
+/// Decodes streams of any sampling frequency to PCM at one fixed rate. A change of frequency
+/// between frames starts a new stream.
+#[derive(Debug, Clone)]
+pub struct RateDecoder<const CHANNELS: usize>
+where
+    Channels<CHANNELS>: ValidChannels,
+{
+    filter4: FilterState<CHANNELS, 4>,
+    filter8: FilterState<CHANNELS, 8>,
+    /// Subbands of the last frame, 0 before the first one.
+    subbands: usize,
+    converter: RateConverter<CHANNELS>,
+}
+
+impl<const CHANNELS: usize> RateDecoder<CHANNELS>
+where
+    Channels<CHANNELS>: ValidChannels,
+{
+    /// Fails if `quality` cannot convert every sampling frequency of SBC to `rate`.
+    pub const fn new(quality: Quality, rate: u32) -> Result<Self, UnsupportedRate> {
+        if let Err(e) = RateConverter::<CHANNELS>::half(quality, 48000, rate) {
+            return Err(e);
+        }
+        let converter = match RateConverter::new(quality, rate, rate) {
+            Ok(converter) => converter,
+            Err(e) => return Err(e),
+        };
+        Ok(Self {
+            filter4: FilterState::new(),
+            filter8: FilterState::new(),
+            subbands: 0,
+            converter,
+        })
+    }
+
+    pub fn converter(&self) -> &RateConverter<CHANNELS> {
+        &self.converter
+    }
+
+    /// Output samples per channel one frame can produce at most, the room
+    /// [`RateDecoder::decode_to_rate`] needs.
+    pub fn max_output(&self) -> usize {
+        (MAX_BLOCKS as u64 * 8 * self.converter.output as u64).div_ceil(16000) as usize + 1
+    }
+
+    fn decode_as<const SUBBANDS: usize, B: ByteInput>(
+        header: &SBCHeader,
+        filter: &mut FilterState<CHANNELS, SUBBANDS>,
+        converter: &mut RateConverter<CHANNELS>,
+        input: &mut B,
+        o: &mut [[i16; CHANNELS]],
+    ) -> Result<usize, FrameDecodeError>
+    where
+        Subbands<SUBBANDS>: ValidSubbands,
+    {
+        let mut blocks = [[[0; SUBBANDS]; CHANNELS]; MAX_BLOCKS];
+        let n = FrameDecoder::new(header, filter, input)?.decode_frame(&mut blocks)?;
+        let blocks = &blocks[..n];
+        let mut pcm = [[0; CHANNELS]; MAX_BLOCKS * 8];
+        let pcm = &mut pcm[..blocks.len() * SUBBANDS];
+        for (b, block) in blocks.iter().enumerate() {
+            for i in 0..SUBBANDS {
+                for ch in 0..CHANNELS {
+                    pcm[b * SUBBANDS + i][ch] = block[ch][i];
+                }
+            }
+        }
+        Ok(converter.process(pcm, o))
+    }
+
+    /// Reads the rest of a frame whose header was read.
+    fn skip<B: ByteInput>(header: &SBCHeader, input: &mut B) -> Result<(), FrameDecodeError> {
+        let mut buf = [0; 64];
+        let mut left = header.frame_length() - 3;
+        while left > 0 {
+            let n = left.min(buf.len());
+            input.read(&mut buf[..n])?;
+            left -= n;
+        }
+        Ok(())
+    }
+
+    /// Reads one frame from `input`, checking its CRC, and writes its PCM converted to the
+    /// output rate to `o`. Returns the number of samples per channel written.
+    ///
+    /// An `o` shorter than [`RateDecoder::max_output`] is refused before anything is read. A
+    /// frame with another number of channels is skipped, so the next call reads the next
+    /// frame. Both are [`FrameDecodeError::SizeBed`].
+    pub fn decode_to_rate<B: ByteInput>(
+        &mut self,
+        input: &mut B,
+        o: &mut [[i16; CHANNELS]],
+    ) -> Result<usize, FrameDecodeError> {
+        if o.len() < self.max_output() {
+            return Err(FrameDecodeError::SizeBed);
+        }
+        let header = SBCHeader::decode(input)?;
+        if header.channel_mode().channels() != CHANNELS {
+            Self::skip(&header, input)?;
+            return Err(FrameDecodeError::SizeBed);
+        }
+        let rate = header.frequency().frequency() as u32;
+        let subbands = header.subbands().subbands();
+        if rate != self.converter.input || subbands != self.subbands {
+            if self.converter.set_input_rate(rate).is_err() {
+                Self::skip(&header, input)?;
+                return Err(FrameDecodeError::SizeBed);
+            }
+            self.filter4.reset();
+            self.filter8.reset();
+            self.subbands = subbands;
+        }
+        match subbands {
+            4 => Self::decode_as(&header, &mut self.filter4, &mut self.converter, input, o),
+            _ => Self::decode_as(&header, &mut self.filter8, &mut self.converter, input, o),
+        }
+    }
+}
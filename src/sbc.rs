@@ -1,12 +1,27 @@
+use crate::frame_decoder::FrameDecodeError;
 use crate::header::{AllocationMethod, ChannelMode, Frequency, SBCHeader};
 use crate::helper;
+use crate::simd;
 use crate::table::{
-    M_0_195, M_0_382, M_0_555, M_0_707, M_0_831, M_0_923, M_0_980, M_1_000, M_PRORO_4, M_PRORO_8,
+    M64_0_195, M64_0_382, M64_0_555, M64_0_707, M64_0_831, M64_0_923, M64_0_980, M64_1_000,
+    M64_PRORO_4, M64_PRORO_8, M_0_195, M_0_382, M_0_555, M_0_707, M_0_831, M_0_923, M_0_980,
+    M_1_000, M_PRORO_4, M_PRORO_8, W_PRORO_4, W_PRORO_8,
 };
 use crunchy::unroll;
 
 pub const FILTER_ORDER: usize = 10;
 
+/// Delay in samples of the analysis and synthesis filter banks together: a sample fed to the
+/// encoder at position `n` comes out of the decoder at `n + codec_delay(subbands)`.
+///
+/// The delay only exists for the pair, it can not be split between encoder and decoder.
+pub const fn codec_delay(subbands: usize) -> usize {
+    (FILTER_ORDER - 1) * subbands + 1
+}
+
+/// `decode64` output is the 16-bit PCM value scaled by `1 << WIDE_SHIFT`.
+pub const WIDE_SHIFT: usize = 38;
+
 pub struct Channels<const V: usize>;
 
 pub trait ValidChannels {}
@@ -20,6 +35,15 @@ pub struct Subbands<const V: usize>;
 pub trait ValidSubbands {
     fn offset(frquency: Frequency, sb: usize) -> i8;
     fn decode16(step: usize, v: &mut [[i32; FILTER_ORDER]], s: &[i32], o: &mut [i16]);
+    fn decode16_simd(
+        step: usize,
+        v: &mut [[i32; simd::ROW]; 2 * FILTER_ORDER],
+        s: &[i32],
+        o: &mut [i16],
+    );
+    fn matrix16(s: &[i32], m: &mut [i32]);
+    fn window16(v: &[i32], o: &mut [i16]);
+    fn decode64(step: usize, v: &mut [[i64; FILTER_ORDER]], s: &[i32], o: &mut [i64]);
 }
 
 impl ValidSubbands for Subbands<4> {
@@ -29,30 +53,18 @@ impl ValidSubbands for Subbands<4> {
         SBC_OFFSET4[frquency as usize][sb]
     }
 
+    #[allow(clippy::erasing_op)]
     fn decode16(step: usize, v: &mut [[i32; FILTER_ORDER]], s: &[i32], o: &mut [i16]) {
         assert!(step < FILTER_ORDER);
         assert_eq!(v.len(), 4);
         assert_eq!(s.len(), 4);
         assert_eq!(o.len(), 4);
 
-        let a03 = s[0] + s[3];
-        let s03 = s[0] - s[3];
-        let a12 = s[1] + s[2];
-        let s12 = s[1] - s[2];
-
-        let v0 = (a03 - a12) * M_0_707; // v[0]
-        let v1 = s03 * M_0_382 - s12 * M_0_923; // v[1]
-        let _v2 = 0; // 0
-        let _v3 = -v1; // -v[1]
-        let _v4 = -v0; // -v[0]
-        let v5 = -(s03 * M_0_923 + s12 * M_0_382); // v[2]
-        let v6 = -((a03 + a12) * M_1_000); // v[3]
-        let _v7 = v5; // v[2]
-
-        v[0][step] = v0 >> 15;
-        v[1][step] = v1 >> 15;
-        v[2][step] = v5 >> 15;
-        v[3][step] = v6 >> 15;
+        let mut m = [0; 4];
+        matrix4(s, &mut m);
+        for k in 0..4 {
+            v[k][step] = m[k];
+        }
 
         let mut sum = [0_i32; 4];
 
@@ -81,6 +93,95 @@ impl ValidSubbands for Subbands<4> {
             o[sb] = helper::saturating_i16(sum[sb] >> 15);
         }
     }
+
+    fn decode16_simd(
+        step: usize,
+        v: &mut [[i32; simd::ROW]; 2 * FILTER_ORDER],
+        s: &[i32],
+        o: &mut [i16],
+    ) {
+        assert!(step < FILTER_ORDER);
+        assert_eq!(s.len(), 4);
+        assert_eq!(o.len(), 4);
+
+        simd::synthesize4(step, v, s, o);
+    }
+
+    fn matrix16(s: &[i32], m: &mut [i32]) {
+        assert_eq!(s.len(), 4);
+        assert_eq!(m.len(), 4);
+
+        matrix4(s, m);
+    }
+
+    fn window16(v: &[i32], o: &mut [i16]) {
+        assert_eq!(v.len(), FILTER_ORDER * 4);
+        assert_eq!(o.len(), 4);
+
+        let mut sum = [0_i32; 4];
+        for f in 0..FILTER_ORDER {
+            let r = &v[(FILTER_ORDER - 1 - f) * 4..][..4];
+            let a = if (f & 1) == 0 {
+                [r[0], r[1], 0, r[1]]
+            } else {
+                [r[0], r[2], r[3], r[2]]
+            };
+            for l in 0..4 {
+                sum[l] += a[l] * W_PRORO_4[f][l];
+            }
+        }
+        for sb in 0..4 {
+            o[sb] = helper::saturating_i16(sum[sb] >> 15);
+        }
+    }
+
+    #[allow(clippy::erasing_op)]
+    fn decode64(step: usize, v: &mut [[i64; FILTER_ORDER]], s: &[i32], o: &mut [i64]) {
+        assert!(step < FILTER_ORDER);
+        assert_eq!(v.len(), 4);
+        assert_eq!(s.len(), 4);
+        assert_eq!(o.len(), 4);
+
+        let a03 = s[0] as i64 + s[3] as i64;
+        let s03 = s[0] as i64 - s[3] as i64;
+        let a12 = s[1] as i64 + s[2] as i64;
+        let s12 = s[1] as i64 - s[2] as i64;
+
+        let v0 = (a03 - a12) * M64_0_707; // v[0]
+        let v1 = s03 * M64_0_382 - s12 * M64_0_923; // v[1]
+        let v5 = -(s03 * M64_0_923 + s12 * M64_0_382); // v[2]
+        let v6 = -((a03 + a12) * M64_1_000); // v[3]
+
+        v[0][step] = v0 >> 24;
+        v[1][step] = v1 >> 24;
+        v[2][step] = v5 >> 24;
+        v[3][step] = v6 >> 24;
+
+        let mut sum = [0_i64; 4];
+
+        let mut i = step;
+        unroll! {
+            for f in 0..10 { // FILTER_ORDER
+                if (f & 1) == 0 {
+                    sum[0] += v[0][i] * M64_PRORO_4[f][0];
+                    sum[1] += v[1][i] * M64_PRORO_4[f][1];
+                    sum[2] += 0 * M64_PRORO_4[f][2];
+                    sum[3] -= v[1][i] * M64_PRORO_4[f][3];
+                } else {
+                    sum[0] -= v[0][i] * M64_PRORO_4[f][0];
+                    sum[1] += v[2][i] * M64_PRORO_4[f][1];
+                    sum[2] += v[3][i] * M64_PRORO_4[f][2];
+                    sum[3] += v[2][i] * M64_PRORO_4[f][3];
+                };
+                if i == 0 {
+                    i = FILTER_ORDER - 1;
+                } else {
+                    i -= 1;
+                }
+            }
+        }
+        o.copy_from_slice(&sum);
+    }
 }
 
 impl ValidSubbands for Subbands<8> {
@@ -94,49 +195,20 @@ impl ValidSubbands for Subbands<8> {
         SBC_OFFSET8[frquency as usize][sb]
     }
 
+    #[allow(clippy::erasing_op)]
     fn decode16(step: usize, v: &mut [[i32; FILTER_ORDER]], s: &[i32], o: &mut [i16]) {
         assert!(step < FILTER_ORDER);
         assert_eq!(v.len(), 8);
         assert_eq!(s.len(), 8);
         assert_eq!(o.len(), 8);
 
-        let a07 = s[0] + s[7];
-        let a16 = s[1] + s[6];
-        let a25 = s[2] + s[5];
-        let a34 = s[3] + s[4];
-        let s07 = s[0] - s[7];
-        let s16 = s[1] - s[6];
-        let s25 = s[2] - s[5];
-        let s34 = s[3] - s[4];
-
-        let v0 = (a07 - a16 - a25 + a34) * M_0_707; // v[0]
-        let v1 = s07 * M_0_555 - s16 * M_0_980 + s25 * M_0_195 + s34 * M_0_831; // v[1]
-        let v2 = (a07 - a34) * M_0_382 + (a25 - a16) * M_0_923; // v[2]
-        let v3 = s07 * M_0_195 - s16 * M_0_555 + s25 * M_0_831 - s34 * M_0_980; // v[3]
-        let _v4 = 0; // 0
-        let _v5 = -v3; // -v[3]
-        let _v6 = -v2; // -v[2]
-        let _v7 = -v1; // -v[1]
-        let _v8 = -v0; // -v[0]
-        let v9 = -s07 * M_0_831 + s16 * M_0_195 + s25 * M_0_980 + s34 * M_0_555; // v[4]
-        let v10 = (a34 - a07) * M_0_923 + (a25 - a16) * M_0_382; // v[5]
-        let v11 = -s07 * M_0_980 - s16 * M_0_831 - s25 * M_0_555 - s34 * M_0_195; // v[6]
-        let v12 = -(a07 + a16 + a25 + a34) * M_1_000; // v[7]
-        let _v13 = v11; // v[6]
-        let _v14 = v10; // v[5]
-        let _v15 = v9; // v[4]
-
-        v[0][step] = v0 >> 15;
-        v[1][step] = v1 >> 15;
-        v[2][step] = v2 >> 15;
-        v[3][step] = v3 >> 15;
-        v[4][step] = v9 >> 15;
-        v[5][step] = v10 >> 15;
-        v[6][step] = v11 >> 15;
-        v[7][step] = v12 >> 15;
+        let mut m = [0; 8];
+        matrix8(s, &mut m);
+        for k in 0..8 {
+            v[k][step] = m[k];
+        }
 
         let mut sum = [0_i32; 8];
-        let mut f = 0;
         let mut i = step;
 
         unroll! {
@@ -171,38 +243,260 @@ impl ValidSubbands for Subbands<8> {
             o[sb] = helper::saturating_i16(sum[sb] >> 15);
         }
     }
+
+    fn decode16_simd(
+        step: usize,
+        v: &mut [[i32; simd::ROW]; 2 * FILTER_ORDER],
+        s: &[i32],
+        o: &mut [i16],
+    ) {
+        assert!(step < FILTER_ORDER);
+        assert_eq!(s.len(), 8);
+        assert_eq!(o.len(), 8);
+
+        simd::synthesize8(step, v, s, o);
+    }
+
+    fn matrix16(s: &[i32], m: &mut [i32]) {
+        assert_eq!(s.len(), 8);
+        assert_eq!(m.len(), 8);
+
+        matrix8(s, m);
+    }
+
+    fn window16(v: &[i32], o: &mut [i16]) {
+        assert_eq!(v.len(), FILTER_ORDER * 8);
+        assert_eq!(o.len(), 8);
+
+        let mut sum = [0_i32; 8];
+        for f in 0..FILTER_ORDER {
+            let r = &v[(FILTER_ORDER - 1 - f) * 8..][..8];
+            let a = if (f & 1) == 0 {
+                [r[0], r[1], r[2], r[3], 0, r[3], r[2], r[1]]
+            } else {
+                [r[0], r[4], r[5], r[6], r[7], r[6], r[5], r[4]]
+            };
+            for l in 0..8 {
+                sum[l] += a[l] * W_PRORO_8[f][l];
+            }
+        }
+        for sb in 0..8 {
+            o[sb] = helper::saturating_i16(sum[sb] >> 15);
+        }
+    }
+
+    #[allow(clippy::erasing_op)]
+    fn decode64(step: usize, v: &mut [[i64; FILTER_ORDER]], s: &[i32], o: &mut [i64]) {
+        assert!(step < FILTER_ORDER);
+        assert_eq!(v.len(), 8);
+        assert_eq!(s.len(), 8);
+        assert_eq!(o.len(), 8);
+
+        let a07 = s[0] as i64 + s[7] as i64;
+        let a16 = s[1] as i64 + s[6] as i64;
+        let a25 = s[2] as i64 + s[5] as i64;
+        let a34 = s[3] as i64 + s[4] as i64;
+        let s07 = s[0] as i64 - s[7] as i64;
+        let s16 = s[1] as i64 - s[6] as i64;
+        let s25 = s[2] as i64 - s[5] as i64;
+        let s34 = s[3] as i64 - s[4] as i64;
+
+        let v0 = (a07 - a16 - a25 + a34) * M64_0_707; // v[0]
+        let v1 = s07 * M64_0_555 - s16 * M64_0_980 + s25 * M64_0_195 + s34 * M64_0_831; // v[1]
+        let v2 = (a07 - a34) * M64_0_382 + (a25 - a16) * M64_0_923; // v[2]
+        let v3 = s07 * M64_0_195 - s16 * M64_0_555 + s25 * M64_0_831 - s34 * M64_0_980; // v[3]
+        let v9 = -s07 * M64_0_831 + s16 * M64_0_195 + s25 * M64_0_980 + s34 * M64_0_555; // v[4]
+        let v10 = (a34 - a07) * M64_0_923 + (a25 - a16) * M64_0_382; // v[5]
+        let v11 = -s07 * M64_0_980 - s16 * M64_0_831 - s25 * M64_0_555 - s34 * M64_0_195; // v[6]
+        let v12 = -(a07 + a16 + a25 + a34) * M64_1_000; // v[7]
+
+        v[0][step] = v0 >> 24;
+        v[1][step] = v1 >> 24;
+        v[2][step] = v2 >> 24;
+        v[3][step] = v3 >> 24;
+        v[4][step] = v9 >> 24;
+        v[5][step] = v10 >> 24;
+        v[6][step] = v11 >> 24;
+        v[7][step] = v12 >> 24;
+
+        let mut sum = [0_i64; 8];
+        let mut i = step;
+
+        unroll! {
+            for f in 0..10 { // FILTER_ORDER
+                if (f & 1) == 0 {
+                    sum[0] += v[0][i] * M64_PRORO_8[f][0];
+                    sum[1] += v[1][i] * M64_PRORO_8[f][1];
+                    sum[2] += v[2][i] * M64_PRORO_8[f][2];
+                    sum[3] += v[3][i] * M64_PRORO_8[f][3];
+                    sum[4] += 0 * M64_PRORO_8[f][4];
+                    sum[5] -= v[3][i] * M64_PRORO_8[f][5];
+                    sum[6] -= v[2][i] * M64_PRORO_8[f][6];
+                    sum[7] -= v[1][i] * M64_PRORO_8[f][7];
+                } else {
+                    sum[0] -= v[0][i] * M64_PRORO_8[f][0];
+                    sum[1] += v[4][i] * M64_PRORO_8[f][1];
+                    sum[2] += v[5][i] * M64_PRORO_8[f][2];
+                    sum[3] += v[6][i] * M64_PRORO_8[f][3];
+                    sum[4] += v[7][i] * M64_PRORO_8[f][4];
+                    sum[5] += v[6][i] * M64_PRORO_8[f][5];
+                    sum[6] += v[5][i] * M64_PRORO_8[f][6];
+                    sum[7] += v[4][i] * M64_PRORO_8[f][7];
+                };
+                if i == 0 {
+                    i = FILTER_ORDER - 1;
+                } else {
+                    i -= 1;
+                }
+            }
+        }
+        o.copy_from_slice(&sum);
+    }
+}
+
+/// Matrixing of one block before the final shift. It is linear in `s`, which the SIMD
+/// tables rely on.
+pub(crate) const fn matrix4_unshifted(s: [i32; 4]) -> [i32; 4] {
+    let a03 = s[0] + s[3];
+    let s03 = s[0] - s[3];
+    let a12 = s[1] + s[2];
+    let s12 = s[1] - s[2];
+
+    let v0 = (a03 - a12) * M_0_707; // v[0]
+    let v1 = s03 * M_0_382 - s12 * M_0_923; // v[1]
+    let _v2 = 0; // 0
+    let _v3 = -v1; // -v[1]
+    let _v4 = -v0; // -v[0]
+    let v5 = -(s03 * M_0_923 + s12 * M_0_382); // v[2]
+    let v6 = -((a03 + a12) * M_1_000); // v[3]
+    let _v7 = v5; // v[2]
+
+    [v0, v1, v5, v6]
+}
+
+fn matrix4(s: &[i32], m: &mut [i32]) {
+    let v = matrix4_unshifted([s[0], s[1], s[2], s[3]]);
+    for k in 0..4 {
+        m[k] = v[k] >> 15;
+    }
+}
+
+/// Matrixing of one block before the final shift, see [`matrix4_unshifted`].
+pub(crate) const fn matrix8_unshifted(s: [i32; 8]) -> [i32; 8] {
+    let a07 = s[0] + s[7];
+    let a16 = s[1] + s[6];
+    let a25 = s[2] + s[5];
+    let a34 = s[3] + s[4];
+    let s07 = s[0] - s[7];
+    let s16 = s[1] - s[6];
+    let s25 = s[2] - s[5];
+    let s34 = s[3] - s[4];
+
+    let v0 = (a07 - a16 - a25 + a34) * M_0_707; // v[0]
+    let v1 = s07 * M_0_555 - s16 * M_0_980 + s25 * M_0_195 + s34 * M_0_831; // v[1]
+    let v2 = (a07 - a34) * M_0_382 + (a25 - a16) * M_0_923; // v[2]
+    let v3 = s07 * M_0_195 - s16 * M_0_555 + s25 * M_0_831 - s34 * M_0_980; // v[3]
+    let _v4 = 0; // 0
+    let _v5 = -v3; // -v[3]
+    let _v6 = -v2; // -v[2]
+    let _v7 = -v1; // -v[1]
+    let _v8 = -v0; // -v[0]
+    let v9 = -s07 * M_0_831 + s16 * M_0_195 + s25 * M_0_980 + s34 * M_0_555; // v[4]
+    let v10 = (a34 - a07) * M_0_923 + (a25 - a16) * M_0_382; // v[5]
+    let v11 = -s07 * M_0_980 - s16 * M_0_831 - s25 * M_0_555 - s34 * M_0_195; // v[6]
+    let v12 = -(a07 + a16 + a25 + a34) * M_1_000; // v[7]
+    let _v13 = v11; // v[6]
+    let _v14 = v10; // v[5]
+    let _v15 = v9; // v[4]
+
+    [v0, v1, v2, v3, v9, v10, v11, v12]
+}
+
+fn matrix8(s: &[i32], m: &mut [i32]) {
+    let v = matrix8_unshifted([s[0], s[1], s[2], s[3], s[4], s[5], s[6], s[7]]);
+    for k in 0..8 {
+        m[k] = v[k] >> 15;
+    }
 }
 
-pub(crate) fn calculate_bits<const CHANNELS: usize, const SUBBANDS: usize>(
+/// Result of the bit allocation of a frame.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct BitAllocation<const CHANNELS: usize, const SUBBANDS: usize> {
+    /// Bits per sample of every subband.
+    pub bits: [[u8; SUBBANDS]; CHANNELS],
+    /// Final bitslice of each channel. Stereo and joint stereo share one bitslice.
+    pub bitslice: [i8; CHANNELS],
+    /// Bits used by one block, summed over all channels.
+    pub bitcount: u16,
+    /// Bits available to one block, summed over all channels: the bitpool, times the
+    /// number of channels for dual channel.
+    pub bitpool: u16,
+    /// Number of subbands, over all channels, that got no bits.
+    pub zero_subbands: usize,
+}
+
+const fn slice_bits(n: i8, bitslice: i8) -> i32 {
+    if n > bitslice + 1 && n < bitslice + 16 {
+        1
+    } else if n == bitslice + 1 {
+        2
+    } else {
+        0
+    }
+}
+
+/// Bit allocation of the A2DP specification (12.6.3).
+///
+/// Mono and dual channel allocate `bitpool` bits to each channel on its own, stereo and
+/// joint stereo share `bitpool` bits between both channels.
+///
+/// Fails with [`FrameDecodeError::SizeBed`] if the header is not for `CHANNELS` channels and
+/// `SUBBANDS` subbands, its bitpool is larger than [`SBCHeader::max_bitpool`], or a scale
+/// factor does not fit in 4 bits.
+pub fn calculate_bits<const CHANNELS: usize, const SUBBANDS: usize>(
     header: &SBCHeader,
     scale_factor: &[[u8; SUBBANDS]; CHANNELS],
-) -> [[u8; SUBBANDS]; CHANNELS]
+) -> Result<BitAllocation<CHANNELS, SUBBANDS>, FrameDecodeError>
 where
     Channels<CHANNELS>: ValidChannels,
     Subbands<SUBBANDS>: ValidSubbands,
 {
-    let mut bits = [[0; SUBBANDS]; CHANNELS];
+    if header.channel_mode().channels() != CHANNELS
+        || header.subbands().subbands() != SUBBANDS
+        || header.bitpool() > header.max_bitpool()
+        || scale_factor.iter().flatten().any(|&sf| sf > 0x0F)
+    {
+        return Err(FrameDecodeError::SizeBed);
+    }
+    let bitpool = header.bitpool() as i32;
+
+    let mut bitneed = [[0_i8; SUBBANDS]; CHANNELS];
+    for ch in 0..CHANNELS {
+        match header.allocation_method() {
+            AllocationMethod::SBC_AM_SNR => {
+                for sb in 0..SUBBANDS {
+                    bitneed[ch][sb] = scale_factor[ch][sb] as i8;
+                }
+            }
+            AllocationMethod::SBC_AM_LOUDNESS => {
+                for sb in 0..SUBBANDS {
+                    if scale_factor[ch][sb] == 0 {
+                        bitneed[ch][sb] = -5;
+                        continue;
+                    }
+                    let loudness = scale_factor[ch][sb] as i8
+                        - <Subbands<SUBBANDS> as ValidSubbands>::offset(header.frequency(), sb);
+                    bitneed[ch][sb] = if loudness > 0 { loudness / 2 } else { loudness };
+                }
+            }
+        }
+    }
+
+    let mut bits = [[0_i8; SUBBANDS]; CHANNELS];
+    let mut bitslices = [0_i8; CHANNELS];
     match header.channel_mode() {
         ChannelMode::SBC_MODE_MONO | ChannelMode::SBC_MODE_DUAL_CHANNEL => {
-            let mut bitneed = [[0; SUBBANDS]; CHANNELS];
             for ch in 0..CHANNELS {
-                match header.allocation_method() {
-                    AllocationMethod::SBC_AM_SNR => {
-                        for sb in 0..SUBBANDS {
-                            bitneed[ch][sb] = scale_factor[ch][sb] as i8;
-                        }
-                    }
-                    AllocationMethod::SBC_AM_LOUDNESS => {
-                        for sb in 0..SUBBANDS {
-                            let loudness = scale_factor[ch][sb] as i8
-                                - <Subbands<SUBBANDS> as ValidSubbands>::offset(
-                                    header.frequency(),
-                                    sb,
-                                );
-                            bitneed[ch][sb] = if loudness > 0 { loudness / 2 } else { loudness };
-                        }
-                    }
-                }
                 let max_bitneed = bitneed[ch].iter().copied().max().unwrap();
 
                 let mut bitcount = 0;
@@ -211,23 +505,15 @@ where
                 loop {
                     bitslice -= 1;
                     bitcount += slicecount;
-                    slicecount = bitneed[ch]
-                        .iter()
-                        .copied()
-                        .map(|n| match n {
-                            n if n > bitslice + 1 && n < bitslice + 16 => 1,
-                            n if n == bitslice + 1 => 2,
-                            _ => 0,
-                        })
-                        .sum();
-                    if bitcount + slicecount >= header.bitpool() {
+                    slicecount = bitneed[ch].iter().map(|&n| slice_bits(n, bitslice)).sum();
+                    if bitcount + slicecount >= bitpool {
                         break;
                     }
                 }
 
-                if bitcount + slicecount < header.bitpool() {
-                    bitslice -= 1;
+                if bitcount + slicecount == bitpool {
                     bitcount += slicecount;
+                    bitslice -= 1;
                 }
 
                 for sb in 0..SUBBANDS {
@@ -239,19 +525,19 @@ where
                 }
 
                 for sb in 0..SUBBANDS {
-                    if bitcount >= header.bitpool() {
+                    if bitcount >= bitpool {
                         break;
                     }
                     if bits[ch][sb] >= 2 && bits[ch][sb] < 16 {
                         bits[ch][sb] += 1;
                         bitcount += 1;
-                    } else if bitneed[ch][sb] == bitslice + 1 && header.bitpool() > bitcount + 1 {
+                    } else if bitneed[ch][sb] == bitslice + 1 && bitpool > bitcount + 1 {
                         bits[ch][sb] = 2;
                         bitcount += 2;
                     }
                 }
                 for sb in 0..SUBBANDS {
-                    if bitcount >= header.bitpool() {
+                    if bitcount >= bitpool {
                         break;
                     }
                     if bits[ch][sb] < 16 {
@@ -259,31 +545,10 @@ where
                         bitcount += 1;
                     }
                 }
+                bitslices[ch] = bitslice;
             }
         }
         ChannelMode::SBC_MODE_STEREO | ChannelMode::SBC_MODE_JOINT_STEREO => {
-            let mut bitneed = [[0; SUBBANDS]; CHANNELS];
-            match header.allocation_method() {
-                AllocationMethod::SBC_AM_SNR => {
-                    for ch in 0..CHANNELS {
-                        for sb in 0..SUBBANDS {
-                            bitneed[ch][sb] = scale_factor[ch][sb] as i8;
-                        }
-                    }
-                }
-                AllocationMethod::SBC_AM_LOUDNESS => {
-                    for ch in 0..CHANNELS {
-                        for sb in 0..SUBBANDS {
-                            let loudness = scale_factor[ch][sb] as i8
-                                - <Subbands<SUBBANDS> as ValidSubbands>::offset(
-                                    header.frequency(),
-                                    sb,
-                                );
-                            bitneed[ch][sb] = if loudness > 0 { loudness / 2 } else { loudness };
-                        }
-                    }
-                }
-            }
             let max_bitneed = bitneed.iter().flatten().copied().max().unwrap();
 
             let mut bitcount = 0;
@@ -292,25 +557,19 @@ where
             loop {
                 bitslice -= 1;
                 bitcount += slicecount;
-                for ch in 0..CHANNELS {
-                    slicecount = bitneed[ch]
-                        .iter()
-                        .copied()
-                        .map(|n| match n {
-                            n if n > bitslice + 1 && n < bitslice + 16 => 1,
-                            n if n == bitslice + 1 => 2,
-                            _ => 0,
-                        })
-                        .sum();
-                }
-                if bitcount + slicecount >= header.bitpool() {
+                slicecount = bitneed
+                    .iter()
+                    .flatten()
+                    .map(|&n| slice_bits(n, bitslice))
+                    .sum();
+                if bitcount + slicecount >= bitpool {
                     break;
                 }
             }
 
-            if bitcount + slicecount < header.bitpool() {
-                bitslice -= 1;
+            if bitcount + slicecount == bitpool {
                 bitcount += slicecount;
+                bitslice -= 1;
             }
 
             for ch in 0..CHANNELS {
@@ -324,13 +583,13 @@ where
             }
             for sb in 0..SUBBANDS {
                 for ch in 0..CHANNELS {
-                    if bitcount >= header.bitpool() {
+                    if bitcount >= bitpool {
                         break;
                     }
                     if bits[ch][sb] >= 2 && bits[ch][sb] < 16 {
                         bits[ch][sb] += 1;
                         bitcount += 1;
-                    } else if bitneed[ch][sb] == bitslice + 1 && header.bitpool() > bitcount + 1 {
+                    } else if bitneed[ch][sb] == bitslice + 1 && bitpool > bitcount + 1 {
                         bits[ch][sb] = 2;
                         bitcount += 2;
                     }
@@ -339,7 +598,7 @@ where
 
             for sb in 0..SUBBANDS {
                 for ch in 0..CHANNELS {
-                    if bitcount >= header.bitpool() {
+                    if bitcount >= bitpool {
                         break;
                     }
                     if bits[ch][sb] < 16 {
@@ -348,14 +607,28 @@ where
                     }
                 }
             }
+            bitslices = [bitslice; CHANNELS];
         }
     }
 
-    let mut r = [[0; SUBBANDS]; CHANNELS];
+    let mut r = BitAllocation {
+        bits: [[0; SUBBANDS]; CHANNELS],
+        bitslice: bitslices,
+        bitcount: 0,
+        bitpool: match header.channel_mode() {
+            ChannelMode::SBC_MODE_DUAL_CHANNEL => bitpool as u16 * CHANNELS as u16,
+            _ => bitpool as u16,
+        },
+        zero_subbands: 0,
+    };
     for ch in 0..CHANNELS {
         for sb in 0..SUBBANDS {
-            r[ch][sb] = bits[ch][sb] as u8;
+            r.bits[ch][sb] = bits[ch][sb] as u8;
+            r.bitcount += bits[ch][sb] as u16;
+            if bits[ch][sb] == 0 {
+                r.zero_subbands += 1;
+            }
         }
     }
-    r
+    Ok(r)
 }
@@ -0,0 +1,330 @@
+//! Matrixing and windowing of the synthesis filter bank for
+//! [`SimdFilterState`](crate::simd_filter_state::SimdFilterState), bit-exact with the scalar
+//! `ValidSubbands::decode16`. The kernel is selected at compile time: AVX2 or SSE2 on x86_64,
+//! NEON on aarch64, portable otherwise.
+//!
+//! The history keeps every matrixed block as one row, already in the order the window reads
+//! it: the lanes of the even taps, then those of the odd taps. Rows are written twice,
+//! `FILTER_ORDER` apart, so that the window of a block reads `FILTER_ORDER` consecutive rows
+//! and every tap is one contiguous load.
+
+use crate::sbc::FILTER_ORDER;
+
+/// Lanes of a history row, enough for 8 subbands.
+pub(crate) const ROW: usize = 16;
+
+pub(crate) type History = [[i32; ROW]; 2 * FILTER_ORDER];
+
+pub(crate) fn synthesize4(step: usize, v: &mut History, s: &[i32], o: &mut [i16]) {
+    #[cfg(all(target_arch = "x86_64", target_feature = "avx2"))]
+    unsafe {
+        x86::synthesize4_avx2(step, v, s, o)
+    }
+    #[cfg(all(target_arch = "x86_64", not(target_feature = "avx2")))]
+    unsafe {
+        x86::synthesize4_sse2(step, v, s, o)
+    }
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        neon::synthesize4(step, v, s, o)
+    }
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    portable::synthesize4(step, v, s, o)
+}
+
+pub(crate) fn synthesize8(step: usize, v: &mut History, s: &[i32], o: &mut [i16]) {
+    #[cfg(all(target_arch = "x86_64", target_feature = "avx2"))]
+    unsafe {
+        x86::synthesize8_avx2(step, v, s, o)
+    }
+    #[cfg(all(target_arch = "x86_64", not(target_feature = "avx2")))]
+    unsafe {
+        x86::synthesize8_sse2(step, v, s, o)
+    }
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        neon::synthesize8(step, v, s, o)
+    }
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    portable::synthesize8(step, v, s, o)
+}
+
+#[allow(dead_code)]
+pub(crate) mod portable {
+    use super::History;
+    use crate::helper;
+    use crate::sbc::FILTER_ORDER;
+    use crate::table::{W_PRORO_4, W_PRORO_8, X_MATRIX_4, X_MATRIX_8};
+
+    fn matrix<const LANES: usize>(x: &[[i32; LANES]], step: usize, v: &mut History, s: &[i32]) {
+        let mut acc = [0_i32; LANES];
+        for (c, &s) in x.iter().zip(s) {
+            for l in 0..LANES {
+                acc[l] = acc[l].wrapping_add(c[l].wrapping_mul(s));
+            }
+        }
+        for l in 0..LANES {
+            acc[l] >>= 15;
+        }
+        v[step][..LANES].copy_from_slice(&acc);
+        v[step + FILTER_ORDER][..LANES].copy_from_slice(&acc);
+    }
+
+    fn window<const SUBBANDS: usize>(
+        w: &[[i32; SUBBANDS]; FILTER_ORDER],
+        step: usize,
+        v: &History,
+        o: &mut [i16],
+    ) {
+        let mut sum = [0_i32; SUBBANDS];
+        for f in 0..FILTER_ORDER {
+            let a = &v[step + FILTER_ORDER - f][(f & 1) * SUBBANDS..][..SUBBANDS];
+            for l in 0..SUBBANDS {
+                sum[l] = sum[l].wrapping_add(a[l].wrapping_mul(w[f][l]));
+            }
+        }
+        for l in 0..SUBBANDS {
+            o[l] = helper::saturating_i16(sum[l] >> 15);
+        }
+    }
+
+    pub(crate) fn synthesize4(step: usize, v: &mut History, s: &[i32], o: &mut [i16]) {
+        matrix(&X_MATRIX_4, step, v, &s[..4]);
+        window(&W_PRORO_4, step, v, &mut o[..4]);
+    }
+
+    pub(crate) fn synthesize8(step: usize, v: &mut History, s: &[i32], o: &mut [i16]) {
+        matrix(&X_MATRIX_8, step, v, &s[..8]);
+        window(&W_PRORO_8, step, v, &mut o[..8]);
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+#[cfg_attr(target_feature = "avx2", allow(dead_code))]
+pub(crate) mod x86 {
+    use super::History;
+    use crate::sbc::FILTER_ORDER;
+    use crate::table::{W_PRORO_4, W_PRORO_8, X_MATRIX_4, X_MATRIX_8};
+    use core::arch::x86_64::*;
+
+    /// Low 32 bits of the lane products, which `_mm_mullo_epi32` only provides from SSE4.1 on.
+    #[inline(always)]
+    unsafe fn mullo_sse2(a: __m128i, b: __m128i) -> __m128i {
+        #[cfg(target_feature = "sse4.1")]
+        {
+            _mm_mullo_epi32(a, b)
+        }
+        #[cfg(not(target_feature = "sse4.1"))]
+        {
+            let even = _mm_mul_epu32(a, b);
+            let odd = _mm_mul_epu32(_mm_srli_si128::<4>(a), _mm_srli_si128::<4>(b));
+            _mm_unpacklo_epi32(
+                _mm_shuffle_epi32::<0b00_00_10_00>(even),
+                _mm_shuffle_epi32::<0b00_00_10_00>(odd),
+            )
+        }
+    }
+
+    #[inline(always)]
+    unsafe fn load(a: &[i32]) -> __m128i {
+        let a = &a[..4];
+        _mm_loadu_si128(a.as_ptr() as *const __m128i)
+    }
+
+    #[inline(always)]
+    unsafe fn store(a: &mut [i32], v: __m128i) {
+        let a = &mut a[..4];
+        _mm_storeu_si128(a.as_mut_ptr() as *mut __m128i, v)
+    }
+
+    #[inline(always)]
+    unsafe fn load256(a: &[i32]) -> __m256i {
+        let a = &a[..8];
+        _mm256_loadu_si256(a.as_ptr() as *const __m256i)
+    }
+
+    #[inline(always)]
+    unsafe fn store256(a: &mut [i32], v: __m256i) {
+        let a = &mut a[..8];
+        _mm256_storeu_si256(a.as_mut_ptr() as *mut __m256i, v)
+    }
+
+    /// Shifts the sums of the window down and saturates them to 16 bits.
+    #[inline(always)]
+    unsafe fn pack(lo: __m128i, hi: __m128i, o: &mut [i16]) {
+        let r = _mm_packs_epi32(_mm_srai_epi32::<15>(lo), _mm_srai_epi32::<15>(hi));
+        let mut t = [0_i16; 8];
+        _mm_storeu_si128(t.as_mut_ptr() as *mut __m128i, r);
+        o.copy_from_slice(&t[..o.len()]);
+    }
+
+    #[target_feature(enable = "sse2")]
+    pub(crate) unsafe fn synthesize4_sse2(step: usize, v: &mut History, s: &[i32], o: &mut [i16]) {
+        let mut m = [_mm_setzero_si128(); 2];
+        for j in 0..4 {
+            let s = _mm_set1_epi32(s[j]);
+            for (r, m) in m.iter_mut().enumerate() {
+                *m = _mm_add_epi32(*m, mullo_sse2(s, load(&X_MATRIX_4[j][4 * r..])));
+            }
+        }
+        for (r, m) in m.iter().enumerate() {
+            let m = _mm_srai_epi32::<15>(*m);
+            store(&mut v[step][4 * r..], m);
+            store(&mut v[step + FILTER_ORDER][4 * r..], m);
+        }
+
+        let mut sum = _mm_setzero_si128();
+        for f in 0..FILTER_ORDER {
+            let a = load(&v[step + FILTER_ORDER - f][(f & 1) * 4..]);
+            sum = _mm_add_epi32(sum, mullo_sse2(a, load(&W_PRORO_4[f])));
+        }
+        pack(sum, _mm_setzero_si128(), &mut o[..4]);
+    }
+
+    #[allow(dead_code)]
+    #[target_feature(enable = "sse2")]
+    pub(crate) unsafe fn synthesize8_sse2(step: usize, v: &mut History, s: &[i32], o: &mut [i16]) {
+        let mut m = [_mm_setzero_si128(); 4];
+        for j in 0..8 {
+            let s = _mm_set1_epi32(s[j]);
+            for (r, m) in m.iter_mut().enumerate() {
+                *m = _mm_add_epi32(*m, mullo_sse2(s, load(&X_MATRIX_8[j][4 * r..])));
+            }
+        }
+        for (r, m) in m.iter().enumerate() {
+            let m = _mm_srai_epi32::<15>(*m);
+            store(&mut v[step][4 * r..], m);
+            store(&mut v[step + FILTER_ORDER][4 * r..], m);
+        }
+
+        let mut lo = _mm_setzero_si128();
+        let mut hi = _mm_setzero_si128();
+        for f in 0..FILTER_ORDER {
+            let a = &v[step + FILTER_ORDER - f][(f & 1) * 8..];
+            lo = _mm_add_epi32(lo, mullo_sse2(load(a), load(&W_PRORO_8[f])));
+            hi = _mm_add_epi32(hi, mullo_sse2(load(&a[4..]), load(&W_PRORO_8[f][4..])));
+        }
+        pack(lo, hi, &mut o[..8]);
+    }
+
+    #[allow(dead_code)]
+    #[target_feature(enable = "avx2")]
+    pub(crate) unsafe fn synthesize4_avx2(step: usize, v: &mut History, s: &[i32], o: &mut [i16]) {
+        let mut m = _mm256_setzero_si256();
+        for j in 0..4 {
+            let s = _mm256_set1_epi32(s[j]);
+            m = _mm256_add_epi32(m, _mm256_mullo_epi32(s, load256(&X_MATRIX_4[j])));
+        }
+        let m = _mm256_srai_epi32::<15>(m);
+        store256(&mut v[step], m);
+        store256(&mut v[step + FILTER_ORDER], m);
+
+        let mut sum = _mm_setzero_si128();
+        for f in 0..FILTER_ORDER {
+            let a = load(&v[step + FILTER_ORDER - f][(f & 1) * 4..]);
+            sum = _mm_add_epi32(sum, _mm_mullo_epi32(a, load(&W_PRORO_4[f])));
+        }
+        pack(sum, _mm_setzero_si128(), &mut o[..4]);
+    }
+
+    #[allow(dead_code)]
+    #[target_feature(enable = "avx2")]
+    pub(crate) unsafe fn synthesize8_avx2(step: usize, v: &mut History, s: &[i32], o: &mut [i16]) {
+        let mut lo = _mm256_setzero_si256();
+        let mut hi = _mm256_setzero_si256();
+        for j in 0..8 {
+            let s = _mm256_set1_epi32(s[j]);
+            lo = _mm256_add_epi32(lo, _mm256_mullo_epi32(s, load256(&X_MATRIX_8[j])));
+            hi = _mm256_add_epi32(hi, _mm256_mullo_epi32(s, load256(&X_MATRIX_8[j][8..])));
+        }
+        let lo = _mm256_srai_epi32::<15>(lo);
+        let hi = _mm256_srai_epi32::<15>(hi);
+        for row in [step, step + FILTER_ORDER] {
+            store256(&mut v[row], lo);
+            store256(&mut v[row][8..], hi);
+        }
+
+        let mut sum = _mm256_setzero_si256();
+        for f in 0..FILTER_ORDER {
+            let a = load256(&v[step + FILTER_ORDER - f][(f & 1) * 8..]);
+            sum = _mm256_add_epi32(sum, _mm256_mullo_epi32(a, load256(&W_PRORO_8[f])));
+        }
+        pack(
+            _mm256_castsi256_si128(sum),
+            _mm256_extracti128_si256::<1>(sum),
+            &mut o[..8],
+        );
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+pub(crate) mod neon {
+    use super::History;
+    use crate::sbc::FILTER_ORDER;
+    use crate::table::{W_PRORO_4, W_PRORO_8, X_MATRIX_4, X_MATRIX_8};
+    use core::arch::aarch64::*;
+
+    #[inline(always)]
+    unsafe fn load(a: &[i32]) -> int32x4_t {
+        vld1q_s32(a[..4].as_ptr())
+    }
+
+    #[inline(always)]
+    unsafe fn store(a: &mut [i32], v: int32x4_t) {
+        vst1q_s32(a[..4].as_mut_ptr(), v)
+    }
+
+    #[target_feature(enable = "neon")]
+    pub(crate) unsafe fn synthesize4(step: usize, v: &mut History, s: &[i32], o: &mut [i16]) {
+        let mut m = [vdupq_n_s32(0); 2];
+        for j in 0..4 {
+            for (r, m) in m.iter_mut().enumerate() {
+                *m = vmlaq_n_s32(*m, load(&X_MATRIX_4[j][4 * r..]), s[j]);
+            }
+        }
+        for (r, m) in m.iter().enumerate() {
+            let m = vshrq_n_s32::<15>(*m);
+            store(&mut v[step][4 * r..], m);
+            store(&mut v[step + FILTER_ORDER][4 * r..], m);
+        }
+
+        let mut sum = vdupq_n_s32(0);
+        for f in 0..FILTER_ORDER {
+            let a = load(&v[step + FILTER_ORDER - f][(f & 1) * 4..]);
+            sum = vmlaq_s32(sum, a, load(&W_PRORO_4[f]));
+        }
+        let mut t = [0_i16; 4];
+        vst1_s16(t.as_mut_ptr(), vqmovn_s32(vshrq_n_s32::<15>(sum)));
+        o[..4].copy_from_slice(&t);
+    }
+
+    #[target_feature(enable = "neon")]
+    pub(crate) unsafe fn synthesize8(step: usize, v: &mut History, s: &[i32], o: &mut [i16]) {
+        let mut m = [vdupq_n_s32(0); 4];
+        for j in 0..8 {
+            for (r, m) in m.iter_mut().enumerate() {
+                *m = vmlaq_n_s32(*m, load(&X_MATRIX_8[j][4 * r..]), s[j]);
+            }
+        }
+        for (r, m) in m.iter().enumerate() {
+            let m = vshrq_n_s32::<15>(*m);
+            store(&mut v[step][4 * r..], m);
+            store(&mut v[step + FILTER_ORDER][4 * r..], m);
+        }
+
+        let mut lo = vdupq_n_s32(0);
+        let mut hi = vdupq_n_s32(0);
+        for f in 0..FILTER_ORDER {
+            let a = &v[step + FILTER_ORDER - f][(f & 1) * 8..];
+            lo = vmlaq_s32(lo, load(a), load(&W_PRORO_8[f]));
+            hi = vmlaq_s32(hi, load(&a[4..]), load(&W_PRORO_8[f][4..]));
+        }
+        let r = vcombine_s16(
+            vqmovn_s32(vshrq_n_s32::<15>(lo)),
+            vqmovn_s32(vshrq_n_s32::<15>(hi)),
+        );
+        let mut t = [0_i16; 8];
+        vst1q_s16(t.as_mut_ptr(), r);
+        o[..8].copy_from_slice(&t);
+    }
+}
@@ -1,6 +1,6 @@
 use crate::const_for;
 use crate::helper::round64;
-use crate::sbc::FILTER_ORDER;
+use crate::sbc::{matrix4_unshifted, matrix8_unshifted, FILTER_ORDER};
 
 #[test]
 fn gen_con_n_pi_d16() {
@@ -67,6 +67,23 @@ pub(crate) const M_0_555: i32 = M_COS_N_PI_D16[5];
 pub(crate) const M_0_382: i32 = M_COS_N_PI_D16[6];
 pub(crate) const M_0_195: i32 = M_COS_N_PI_D16[7];
 
+const M64_COS_N_PI_D16: [i64; 9] = {
+    let mut v = [0; 9];
+    const_for!(i in (0, 9) {
+        v[i] = round64(COS_N_PI_D16[i], 32);
+    });
+    v
+};
+
+pub(crate) const M64_1_000: i64 = M64_COS_N_PI_D16[0];
+pub(crate) const M64_0_980: i64 = M64_COS_N_PI_D16[1];
+pub(crate) const M64_0_923: i64 = M64_COS_N_PI_D16[2];
+pub(crate) const M64_0_831: i64 = M64_COS_N_PI_D16[3];
+pub(crate) const M64_0_707: i64 = M64_COS_N_PI_D16[4];
+pub(crate) const M64_0_555: i64 = M64_COS_N_PI_D16[5];
+pub(crate) const M64_0_382: i64 = M64_COS_N_PI_D16[6];
+pub(crate) const M64_0_195: i64 = M64_COS_N_PI_D16[7];
+
 #[test]
 fn show_syn_matrix4() {
     for n in 0..8 {
@@ -113,7 +130,8 @@ fn show_syn_matrix8() {
     }
 }
 
-const F_PROTO_4: [[&'static str; 4]; FILTER_ORDER] = {
+#[allow(dead_code)]
+pub(crate) const F_PROTO_4: [[&str; 4]; FILTER_ORDER] = {
     [
         [
             "+0.00000000E+00",
@@ -292,7 +310,51 @@ pub(crate) const M_PRORO_4: [[i32; 4]; FILTER_ORDER] = {
     v
 };
 
-const F_PROTO_8: [[&'static str; 8]; FILTER_ORDER] = {
+/// `M_PRORO_4` with the signs of the windowing folded in, for the SIMD kernels.
+pub(crate) const W_PRORO_4: [[i32; 4]; FILTER_ORDER] = {
+    const SIGN: [[i32; 4]; 2] = [[1, 1, 1, -1], [-1, 1, 1, 1]];
+    let mut v = [[0; 4]; FILTER_ORDER];
+    const_for!(i in (0, FILTER_ORDER) {
+        const_for!(sb in (0, 4) {
+            v[i][sb] = M_PRORO_4[i][sb] * SIGN[i & 1][sb];
+        });
+    });
+    v
+};
+
+/// Matrixing laid out for the SIMD kernels: per input subband, its coefficient in every lane
+/// of a history row. A row holds the outputs in the order the even taps of the window read
+/// them, then the odd taps, with lane `ZERO` left empty.
+pub(crate) const X_MATRIX_4: [[i32; 8]; 4] = {
+    const LANES: [usize; 8] = [0, 1, ZERO, 1, 0, 2, 3, 2];
+    let mut v = [[0; 8]; 4];
+    const_for!(j in (0, 4) {
+        let mut s = [0; 4];
+        s[j] = 1;
+        let m = matrix4_unshifted(s);
+        const_for!(l in (0, 8) {
+            if LANES[l] != ZERO {
+                v[j][l] = m[LANES[l]];
+            }
+        });
+    });
+    v
+};
+
+const ZERO: usize = usize::MAX;
+
+pub(crate) const M64_PRORO_4: [[i64; 4]; FILTER_ORDER] = {
+    let mut v = [[0; 4]; FILTER_ORDER];
+    const_for!(i in (0, FILTER_ORDER) {
+        const_for!(sb in (0, 4) {
+            v[i][sb] = round64(PROTO_4[i][sb], 32);
+        });
+    });
+    v
+};
+
+#[allow(dead_code)]
+pub(crate) const F_PROTO_8: [[&str; 8]; FILTER_ORDER] = {
     [
         [
             "+0.00000000E+00",
@@ -550,3 +612,247 @@ pub(crate) const M_PRORO_8: [[i32; 8]; FILTER_ORDER] = {
     });
     v
 };
+
+/// `M_PRORO_8` with the signs of the windowing folded in, for the SIMD kernels.
+pub(crate) const W_PRORO_8: [[i32; 8]; FILTER_ORDER] = {
+    const SIGN: [[i32; 8]; 2] = [[1, 1, 1, 1, 1, -1, -1, -1], [-1, 1, 1, 1, 1, 1, 1, 1]];
+    let mut v = [[0; 8]; FILTER_ORDER];
+    const_for!(i in (0, FILTER_ORDER) {
+        const_for!(sb in (0, 8) {
+            v[i][sb] = M_PRORO_8[i][sb] * SIGN[i & 1][sb];
+        });
+    });
+    v
+};
+
+/// See [`X_MATRIX_4`].
+pub(crate) const X_MATRIX_8: [[i32; 16]; 8] = {
+    const LANES: [usize; 16] = [0, 1, 2, 3, ZERO, 3, 2, 1, 0, 4, 5, 6, 7, 6, 5, 4];
+    let mut v = [[0; 16]; 8];
+    const_for!(j in (0, 8) {
+        let mut s = [0; 8];
+        s[j] = 1;
+        let m = matrix8_unshifted(s);
+        const_for!(l in (0, 16) {
+            if LANES[l] != ZERO {
+                v[j][l] = m[LANES[l]];
+            }
+        });
+    });
+    v
+};
+
+pub(crate) const M64_PRORO_8: [[i64; 8]; FILTER_ORDER] = {
+    let mut v = [[0; 8]; FILTER_ORDER];
+    const_for!(i in (0, FILTER_ORDER) {
+        const_for!(sb in (0, 8) {
+            v[i][sb] = round64(PROTO_8[i][sb], 32);
+        });
+    });
+    v
+};
+
+/// Zero crossings on each side of the interpolation kernel of the sinc resampler.
+pub(crate) const SINC_ZERO_CROSSINGS: usize = 8;
+/// Entries of [`SINC_TABLE`] per zero crossing.
+pub(crate) const SINC_RESOLUTION: usize = 64;
+
+#[test]
+fn gen_sinc_table() {
+    // Cutoff relative to the Nyquist frequency, leaving room for the transition band.
+    const SINC_CUTOFF: f64 = 0.9;
+    const SINC_KAISER_BETA: f64 = 7.0;
+    fn bessel_i0(x: f64) -> f64 {
+        let mut sum = 1.0;
+        let mut term = 1.0;
+        for k in 1..50 {
+            term *= (x / 2.0) / k as f64;
+            sum += term * term;
+        }
+        sum
+    }
+    let len = SINC_ZERO_CROSSINGS * SINC_RESOLUTION + 1;
+    println!("pub(crate) const SINC_TABLE: [i16; {}] = [", len);
+    for i in 0..len {
+        let u = i as f64 / SINC_RESOLUTION as f64;
+        let x = core::f64::consts::PI * SINC_CUTOFF * u;
+        let sinc = if i == 0 { 1.0 } else { x.sin() / x };
+        let r = u / SINC_ZERO_CROSSINGS as f64;
+        let window = bessel_i0(SINC_KAISER_BETA * (1.0 - r * r).max(0.0).sqrt())
+            / bessel_i0(SINC_KAISER_BETA);
+        let v = SINC_CUTOFF * sinc * window * 32768.0;
+        if i % 8 == 0 {
+            print!("   ");
+        }
+        print!(" {},", v.round() as i16);
+        if i % 8 == 7 || i == len - 1 {
+            println!();
+        }
+    }
+    println!("];");
+}
+
+/// Kaiser windowed sinc cut off at 0.9 times the Nyquist frequency, in Q15, sampled
+/// [`SINC_RESOLUTION`] times per zero crossing. Printed by `gen_sinc_table`.
+pub(crate) const SINC_TABLE: [i16; 513] = [
+    29491, 29481, 29451, 29402, 29332, 29243, 29134, 29006, 28858, 28692, 28506, 28302, 28079,
+    27838, 27580, 27304, 27010, 26700, 26374, 26032, 25674, 25301, 24913, 24511, 24096, 23667,
+    23226, 22773, 22308, 21832, 21346, 20850, 20345, 19831, 19309, 18780, 18244, 17703, 17155,
+    16603, 16047, 15487, 14925, 14360, 13793, 13226, 12658, 12091, 11524, 10959, 10397, 9837, 9280,
+    8727, 8179, 7636, 7099, 6567, 6043, 5525, 5016, 4514, 4021, 3538, 3064, 2599, 2145, 1702, 1270,
+    849, 440, 43, -341, -713, -1073, -1419, -1752, -2072, -2378, -2670, -2949, -3214, -3465, -3702,
+    -3925, -4133, -4328, -4509, -4676, -4829, -4969, -5094, -5207, -5306, -5391, -5464, -5524,
+    -5571, -5606, -5628, -5639, -5638, -5626, -5603, -5570, -5525, -5471, -5407, -5334, -5252,
+    -5161, -5062, -4955, -4840, -4719, -4590, -4456, -4315, -4169, -4018, -3862, -3702, -3539,
+    -3371, -3201, -3028, -2852, -2675, -2496, -2316, -2136, -1955, -1773, -1593, -1413, -1234,
+    -1056, -880, -706, -534, -365, -199, -36, 124, 280, 432, 581, 725, 864, 999, 1129, 1254, 1374,
+    1488, 1597, 1701, 1799, 1892, 1978, 2059, 2134, 2203, 2267, 2324, 2376, 2421, 2461, 2495, 2523,
+    2546, 2562, 2574, 2580, 2580, 2575, 2565, 2551, 2531, 2506, 2477, 2443, 2406, 2364, 2318, 2268,
+    2215, 2158, 2098, 2035, 1969, 1900, 1829, 1756, 1680, 1603, 1524, 1443, 1362, 1279, 1195, 1110,
+    1025, 940, 854, 768, 683, 598, 513, 429, 346, 264, 183, 104, 26, -51, -126, -199, -270, -339,
+    -406, -471, -534, -594, -651, -706, -759, -809, -856, -900, -942, -980, -1016, -1049, -1080,
+    -1107, -1132, -1153, -1172, -1188, -1201, -1212, -1219, -1224, -1227, -1227, -1224, -1219,
+    -1211, -1201, -1189, -1175, -1158, -1140, -1119, -1097, -1073, -1047, -1019, -990, -960, -928,
+    -895, -861, -826, -790, -753, -716, -678, -639, -600, -560, -520, -480, -440, -400, -360, -320,
+    -280, -241, -202, -163, -125, -88, -52, -16, 19, 54, 87, 119, 150, 181, 210, 238, 265, 290,
+    315, 338, 360, 381, 400, 418, 435, 450, 464, 477, 488, 498, 507, 515, 521, 526, 529, 532, 533,
+    533, 532, 530, 527, 523, 518, 512, 504, 496, 488, 478, 467, 456, 444, 432, 419, 405, 391, 376,
+    361, 346, 330, 314, 298, 282, 265, 248, 231, 214, 198, 181, 164, 147, 131, 115, 98, 83, 67, 52,
+    37, 22, 8, -6, -20, -33, -45, -57, -69, -80, -91, -101, -110, -119, -128, -136, -143, -150,
+    -157, -163, -168, -173, -177, -181, -184, -186, -189, -190, -191, -192, -192, -192, -192, -191,
+    -189, -188, -185, -183, -180, -177, -173, -170, -166, -162, -157, -152, -148, -143, -137, -132,
+    -127, -121, -115, -110, -104, -98, -92, -87, -81, -75, -69, -64, -58, -52, -47, -41, -36, -31,
+    -26, -21, -16, -12, -7, -3, 1, 5, 9, 13, 16, 20, 23, 26, 28, 31, 33, 36, 38, 39, 41, 43, 44,
+    45, 46, 47, 48, 48, 48, 49, 49, 49, 49, 48, 48, 47, 47, 46, 45, 44, 43, 42, 41, 40, 39, 38, 36,
+    35, 34, 32, 31, 30, 28, 27, 25, 24, 22, 21, 20, 18, 17, 16, 14, 13, 12, 11, 10, 8, 7, 6, 5, 4,
+    4, 3, 2, 1, 1, 0, -1, -1, -2, -2, -3, -3, -3, -4, -4, -4, -4, -4, -4, -5,
+];
+
+#[test]
+fn gen_analysis_tables() {
+    for (m, proto) in [
+        (4, F_PROTO_4.iter().flatten().collect::<Vec<_>>()),
+        (8, F_PROTO_8.iter().flatten().collect::<Vec<_>>()),
+    ] {
+        println!("pub(crate) const A_PROTO_{}: [i32; {}] = [", m, 10 * m);
+        for (i, v) in proto.iter().enumerate() {
+            let f: f64 = v.parse().unwrap();
+            print!(
+                "{}{:#010X},",
+                if i % 8 == 0 { "    " } else { " " },
+                (f * (1_u64 << 31) as f64).round() as i32
+            );
+            if i % 8 == 7 {
+                println!();
+            }
+        }
+        println!("];");
+        println!(
+            "pub(crate) const A_COS_{}: [[i32; {}]; {}] = [",
+            m,
+            2 * m,
+            m
+        );
+        for k in 0..m {
+            print!("    [");
+            for i in 0..2 * m {
+                let f = (core::f64::consts::PI / m as f64
+                    * (k as f64 + 0.5)
+                    * (i as f64 - m as f64 / 2.0))
+                    .cos();
+                print!("{:#010X}, ", (f * (1 << 28) as f64).round() as i32);
+            }
+            println!("],");
+        }
+        println!("];");
+    }
+}
+
+/// Analysis window of the encoder, the prototype filter in Q31.
+#[allow(overflowing_literals)]
+pub(crate) const A_PROTO_4: [i32; 40] = [
+    0x00000000, 0x001194E6, 0x0030E2D4, 0x00599403, 0x007DBCC8, 0x007F88E5, 0x003D239B, 0xFF9BB9D4,
+    0x01659F45, 0x029DBAA3, 0x03B23342, 0x041EEE40, 0x034FEE2C, 0x00C8F2BC, 0xFC4F91D4, 0xF60FAF36,
+    0x115B1ED3, 0x18F55C90, 0x1F91CA47, 0x2412F251, 0x25AC1FF3, 0x2412F251, 0x1F91CA47, 0x18F55C90,
+    0xEEA4E12D, 0xF60FAF36, 0xFC4F91D4, 0x00C8F2BC, 0x034FEE2C, 0x041EEE40, 0x03B23342, 0x029DBAA3,
+    0xFE9A60BB, 0xFF9BB9D4, 0x003D239B, 0x007F88E5, 0x007DBCC8, 0x00599403, 0x0030E2D4, 0x001194E6,
+];
+
+/// Analysis matrix `cos((k + 0.5) * (i - M / 2) * pi / M)` in Q28.
+#[allow(overflowing_literals)]
+pub(crate) const A_COS_4: [[i32; 8]; 4] = [
+    [
+        0x0B504F33, 0x0EC835E8, 0x10000000, 0x0EC835E8, 0x0B504F33, 0x061F78AA, 0x00000000,
+        0xF9E08756,
+    ],
+    [
+        0xF4AFB0CD, 0x061F78AA, 0x10000000, 0x061F78AA, 0xF4AFB0CD, 0xF137CA18, 0x00000000,
+        0x0EC835E8,
+    ],
+    [
+        0xF4AFB0CD, 0xF9E08756, 0x10000000, 0xF9E08756, 0xF4AFB0CD, 0x0EC835E8, 0x00000000,
+        0xF137CA18,
+    ],
+    [
+        0x0B504F33, 0xF137CA18, 0x10000000, 0xF137CA18, 0x0B504F33, 0xF9E08756, 0x00000000,
+        0x061F78AA,
+    ],
+];
+
+#[allow(overflowing_literals)]
+pub(crate) const A_PROTO_8: [i32; 80] = [
+    0x00000000, 0x00052173, 0x000B3F72, 0x00122C7E, 0x001AFF8A, 0x00255A62, 0x003060F4, 0x003A72E8,
+    0x0041EC6A, 0x0044EF48, 0x00415B76, 0x0034F8B6, 0x001D8FD2, 0xFFFA2412, 0xFFC9F10E, 0xFF8D6793,
+    0x00B97348, 0x01071B96, 0x0156B3CA, 0x01A1B38B, 0x01E0224D, 0x0209291F, 0x02138654, 0x01F5F424,
+    0x01A7ECF0, 0x01223EBA, 0x005FD0FF, 0xFF5EEB72, 0xFE20435C, 0xFCA86E7E, 0xFAFF95FC, 0xF9312890,
+    0x08B4307A, 0x0A9F3E9A, 0x0C7D59B6, 0x0E3BB16F, 0x0FC721F9, 0x110ECEF0, 0x120435FA, 0x129C226F,
+    0x12CF6C76, 0x129C226F, 0x120435FA, 0x110ECEF0, 0x0FC721F9, 0x0E3BB16F, 0x0C7D59B6, 0x0A9F3E9A,
+    0xF74BCF86, 0xF9312890, 0xFAFF95FC, 0xFCA86E7E, 0xFE20435C, 0xFF5EEB72, 0x005FD0FF, 0x01223EBA,
+    0x01A7ECF0, 0x01F5F424, 0x02138654, 0x0209291F, 0x01E0224D, 0x01A1B38B, 0x0156B3CA, 0x01071B96,
+    0xFF468CB8, 0xFF8D6793, 0xFFC9F10E, 0xFFFA2412, 0x001D8FD2, 0x0034F8B6, 0x00415B76, 0x0044EF48,
+    0x0041EC6A, 0x003A72E8, 0x003060F4, 0x00255A62, 0x001AFF8A, 0x00122C7E, 0x000B3F72, 0x00052173,
+];
+
+#[allow(overflowing_literals)]
+pub(crate) const A_COS_8: [[i32; 16]; 8] = [
+    [
+        0x0B504F33, 0x0D4DB315, 0x0EC835E8, 0x0FB14BE8, 0x10000000, 0x0FB14BE8, 0x0EC835E8,
+        0x0D4DB315, 0x0B504F33, 0x08E39D9D, 0x061F78AA, 0x031F1708, 0x00000000, 0xFCE0E8F8,
+        0xF9E08756, 0xF71C6263,
+    ],
+    [
+        0xF4AFB0CD, 0xFCE0E8F8, 0x061F78AA, 0x0D4DB315, 0x10000000, 0x0D4DB315, 0x061F78AA,
+        0xFCE0E8F8, 0xF4AFB0CD, 0xF04EB418, 0xF137CA18, 0xF71C6263, 0x00000000, 0x08E39D9D,
+        0x0EC835E8, 0x0FB14BE8,
+    ],
+    [
+        0xF4AFB0CD, 0xF04EB418, 0xF9E08756, 0x08E39D9D, 0x10000000, 0x08E39D9D, 0xF9E08756,
+        0xF04EB418, 0xF4AFB0CD, 0x031F1708, 0x0EC835E8, 0x0D4DB315, 0x00000000, 0xF2B24CEB,
+        0xF137CA18, 0xFCE0E8F8,
+    ],
+    [
+        0x0B504F33, 0xF71C6263, 0xF137CA18, 0x031F1708, 0x10000000, 0x031F1708, 0xF137CA18,
+        0xF71C6263, 0x0B504F33, 0x0D4DB315, 0xF9E08756, 0xF04EB418, 0x00000000, 0x0FB14BE8,
+        0x061F78AA, 0xF2B24CEB,
+    ],
+    [
+        0x0B504F33, 0x08E39D9D, 0xF137CA18, 0xFCE0E8F8, 0x10000000, 0xFCE0E8F8, 0xF137CA18,
+        0x08E39D9D, 0x0B504F33, 0xF2B24CEB, 0xF9E08756, 0x0FB14BE8, 0x00000000, 0xF04EB418,
+        0x061F78AA, 0x0D4DB315,
+    ],
+    [
+        0xF4AFB0CD, 0x0FB14BE8, 0xF9E08756, 0xF71C6263, 0x10000000, 0xF71C6263, 0xF9E08756,
+        0x0FB14BE8, 0xF4AFB0CD, 0xFCE0E8F8, 0x0EC835E8, 0xF2B24CEB, 0x00000000, 0x0D4DB315,
+        0xF137CA18, 0x031F1708,
+    ],
+    [
+        0xF4AFB0CD, 0x031F1708, 0x061F78AA, 0xF2B24CEB, 0x10000000, 0xF2B24CEB, 0x061F78AA,
+        0x031F1708, 0xF4AFB0CD, 0x0FB14BE8, 0xF137CA18, 0x08E39D9D, 0x00000000, 0xF71C6263,
+        0x0EC835E8, 0xF04EB418,
+    ],
+    [
+        0x0B504F33, 0xF2B24CEB, 0x0EC835E8, 0xF04EB418, 0x10000000, 0xF04EB418, 0x0EC835E8,
+        0xF2B24CEB, 0x0B504F33, 0xF71C6263, 0x061F78AA, 0xFCE0E8F8, 0x00000000, 0x031F1708,
+        0xF9E08756, 0x08E39D9D,
+    ],
+];
@@ -0,0 +1,186 @@
+//! Sample positions of decoded frames and blocks, kept in step with the RTP timestamps of A2DP
+//! or the H2 sequence numbers of mSBC.
+
+use crate::header::{Frequency, SBCHeader, MSBC_BLOCKS};
+
+/// Samples per mSBC frame, the H2 sequence number advances once per frame.
+pub const MSBC_FRAME_SAMPLES: u64 = (MSBC_BLOCKS * 8) as u64;
+
+/// Second byte of the H2 header for sequence numbers 0 to 3. The first byte is always `0x01`.
+pub const H2_SEQUENCE: [u8; 4] = [0x08, 0x38, 0xC8, 0xF8];
+
+/// Sequence number of an H2 header.
+pub fn decode_h2(header: [u8; 2]) -> Option<u8> {
+    if header[0] != 0x01 {
+        return None;
+    }
+    H2_SEQUENCE
+        .iter()
+        .position(|&b| b == header[1])
+        .map(|sn| sn as u8)
+}
+
+pub const fn encode_h2(sn: u8) -> [u8; 2] {
+    [0x01, H2_SEQUENCE[(sn & 3) as usize]]
+}
+
+/// How the position given by the transport relates to where the stream was expected to be.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Continuity {
+    Continuous,
+    /// This many samples are missing before the packet.
+    Gap(u64),
+    /// The packet repeats this many samples that were already played.
+    Overlap(u64),
+}
+
+/// Position of one frame on the timeline.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct FrameTime {
+    /// Sample position of the first sample of the frame.
+    pub pts: u64,
+    pub blocks: usize,
+    pub subbands: usize,
+}
+
+impl FrameTime {
+    /// Sample position of the first sample of block `block`.
+    pub const fn block_pts(&self, block: usize) -> u64 {
+        self.pts + (block * self.subbands) as u64
+    }
+
+    /// Sample position just past the frame.
+    pub const fn end(&self) -> u64 {
+        self.block_pts(self.blocks)
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+enum Origin {
+    None,
+    Rtp { position: u64, timestamp: u32 },
+    H2 { position: u64, sn: u8 },
+}
+
+/// Counts samples over the frames of a stream, starting at 0.
+///
+/// Call [`Timeline::rtp_packet`] or [`Timeline::h2_frame`] when a packet arrives and then
+/// [`Timeline::frame`] for each frame it carries. The first packet fixes how transport time maps
+/// to sample positions; later ones that do not continue the stream move the position to where
+/// the transport says they belong and report the discontinuity.
+#[derive(Debug, Copy, Clone)]
+pub struct Timeline {
+    position: u64,
+    origin: Origin,
+}
+
+impl Default for Timeline {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Timeline {
+    pub const fn new() -> Self {
+        Self {
+            position: 0,
+            origin: Origin::None,
+        }
+    }
+
+    /// Sample position of the next frame.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Takes the next frame and returns where it lies.
+    pub fn frame(&mut self, header: &SBCHeader) -> FrameTime {
+        let subbands = header.subbands().subbands();
+        let time = FrameTime {
+            pts: self.position,
+            blocks: header.frame_samples() / subbands,
+            subbands,
+        };
+        self.position = time.end();
+        time
+    }
+
+    fn jump(&mut self, delta: i64) -> Continuity {
+        if delta > 0 {
+            self.position += delta as u64;
+            Continuity::Gap(delta as u64)
+        } else if delta < 0 {
+            let delta = delta.unsigned_abs().min(self.position);
+            self.position -= delta;
+            Continuity::Overlap(delta)
+        } else {
+            Continuity::Continuous
+        }
+    }
+
+    /// Starts an RTP packet with `timestamp`, in samples at the stream's sampling rate.
+    pub fn rtp_packet(&mut self, timestamp: u32) -> Continuity {
+        match self.origin {
+            Origin::Rtp { .. } => {
+                let expected = self.rtp_timestamp(self.position);
+                self.jump(timestamp.wrapping_sub(expected) as i32 as i64)
+            }
+            _ => {
+                self.origin = Origin::Rtp {
+                    position: self.position,
+                    timestamp,
+                };
+                Continuity::Continuous
+            }
+        }
+    }
+
+    /// Starts an mSBC frame with H2 sequence number `sn`. With only four sequence numbers,
+    /// up to two lost frames are reported as a gap and a repeated frame as an overlap.
+    pub fn h2_frame(&mut self, sn: u8) -> Continuity {
+        let sn = sn & 3;
+        match self.origin {
+            Origin::H2 { .. } => {
+                let expected = self.h2_sequence(self.position);
+                let frames = match sn.wrapping_sub(expected) & 3 {
+                    3 => -1,
+                    n => n as i64,
+                };
+                self.jump(frames * MSBC_FRAME_SAMPLES as i64)
+            }
+            _ => {
+                self.origin = Origin::H2 {
+                    position: self.position,
+                    sn,
+                };
+                Continuity::Continuous
+            }
+        }
+    }
+
+    /// RTP timestamp of sample position `position`, once the first RTP packet has been seen.
+    pub fn rtp_timestamp(&self, position: u64) -> u32 {
+        match self.origin {
+            Origin::Rtp {
+                position: p,
+                timestamp,
+            } => timestamp.wrapping_add(position.wrapping_sub(p) as u32),
+            _ => position as u32,
+        }
+    }
+
+    /// H2 sequence number of the mSBC frame starting at sample position `position`.
+    pub fn h2_sequence(&self, position: u64) -> u8 {
+        let (p, sn) = match self.origin {
+            Origin::H2 { position, sn } => (position, sn),
+            _ => (0, 0),
+        };
+        let frames = (position as i64 - p as i64).div_euclid(MSBC_FRAME_SAMPLES as i64);
+        (sn as i64 + frames).rem_euclid(4) as u8
+    }
+}
+
+/// Sample position `position` as microseconds at `frequency`.
+pub fn to_micros(position: u64, frequency: Frequency) -> u64 {
+    (position as u128 * 1_000_000 / frequency.frequency() as u128) as u64
+}
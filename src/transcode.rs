@@ -0,0 +1,116 @@
+//! Bitpool transcoding on the bitstream: the subband samples are requantized to a new
+//! allocation without running either filter bank.
+
+use crate::frame::Frame;
+use crate::frame_decoder::FrameDecodeError;
+use crate::header::{SBCHeader, MAX_BLOCKS};
+use crate::io::{ByteInput, ByteOutput};
+use crate::sbc::{calculate_bits, Channels, Subbands, ValidChannels, ValidSubbands};
+
+/// Maps a `bits` wide quantized sample to the `new_bits` level holding the same value.
+///
+/// Level `q` of `L = 2^bits - 1` levels stands for the middle of `[q / L, (q + 1) / L)` of
+/// the normalised range, so the new level is `floor((2q + 1) * L' / 2L)`. With `bits == 0`
+/// the sample is silent and maps to the middle level.
+pub const fn requantize_sample(q: u16, bits: u8, new_bits: u8) -> u16 {
+    if bits == new_bits {
+        return q;
+    }
+    if new_bits == 0 {
+        return 0;
+    }
+    let new_levels = (1_u32 << new_bits) - 1;
+    if bits == 0 {
+        return (new_levels >> 1) as u16;
+    }
+    let levels = (1_u32 << bits) - 1;
+    let v = ((2 * q as u64 + 1) * new_levels as u64) / (2 * levels as u64);
+    if v >= new_levels as u64 {
+        (new_levels - 1) as u16
+    } else {
+        v as u16
+    }
+}
+
+/// Requantizes `frame` for `bitpool`, keeping its scale factors and joint stereo decisions.
+pub fn requantize<const CHANNELS: usize, const SUBBANDS: usize>(
+    frame: &Frame<CHANNELS, SUBBANDS>,
+    bitpool: u8,
+) -> Result<Frame<CHANNELS, SUBBANDS>, FrameDecodeError>
+where
+    Channels<CHANNELS>: ValidChannels,
+    Subbands<SUBBANDS>: ValidSubbands,
+{
+    let header = frame
+        .header()
+        .with_bitpool(bitpool)
+        .ok_or(FrameDecodeError::SizeBed)?;
+    if bitpool > header.max_bitpool() {
+        return Err(FrameDecodeError::SizeBed);
+    }
+    let bits = calculate_bits(&header, frame.scale_factor())?.bits;
+    let old_bits = frame.bits();
+    let mut quantized = [[[0; SUBBANDS]; CHANNELS]; MAX_BLOCKS];
+    let quantized = &mut quantized[..frame.blocks()];
+    quantized.copy_from_slice(frame.quantized());
+    for q in quantized.iter_mut() {
+        for ch in 0..CHANNELS {
+            for sb in 0..SUBBANDS {
+                q[ch][sb] = requantize_sample(q[ch][sb], old_bits[ch][sb], bits[ch][sb]);
+            }
+        }
+    }
+    Ok(Frame::from_parts(
+        &header,
+        frame.joint(),
+        frame.scale_factor(),
+        &bits,
+        quantized,
+    ))
+}
+
+fn transcode_as<const CHANNELS: usize, const SUBBANDS: usize, B: ByteInput, O: ByteOutput>(
+    header: &SBCHeader,
+    input: &mut B,
+    bitpool: u8,
+    output: &mut O,
+) -> Result<SBCHeader, FrameDecodeError>
+where
+    Channels<CHANNELS>: ValidChannels,
+    Subbands<SUBBANDS>: ValidSubbands,
+{
+    let frame = Frame::<CHANNELS, SUBBANDS>::decode(header, input)?;
+    let frame = requantize(&frame, bitpool)?;
+    frame.encode(output)?;
+    Ok(*frame.header())
+}
+
+/// Reads one frame from `input`, checking its CRC, and writes it to `output` requantized for
+/// `bitpool` with a new CRC. Returns the header of the written frame.
+///
+/// Lowering the bitpool is lossy. Transcoding to the same bitpool reproduces the frame bit
+/// for bit, apart from the padding at its end.
+pub fn transcode<B: ByteInput, O: ByteOutput>(
+    input: &mut B,
+    bitpool: u8,
+    output: &mut O,
+) -> Result<SBCHeader, FrameDecodeError> {
+    let header = SBCHeader::decode(input)?;
+    let length = header
+        .with_bitpool(bitpool)
+        .filter(|h| bitpool <= h.max_bitpool())
+        .map(|h| h.frame_length());
+    if length.is_some_and(|length| output.remaining().is_some_and(|n| n < length)) {
+        return Err(FrameDecodeError::ByteError(()));
+    }
+    match (
+        header.channel_mode().channels(),
+        header.subbands().subbands(),
+    ) {
+        (1, 4) => transcode_as::<1, 4, B, O>(&header, input, bitpool, output),
+        (1, 8) => transcode_as::<1, 8, B, O>(&header, input, bitpool, output),
+        (2, 4) => transcode_as::<2, 4, B, O>(&header, input, bitpool, output),
+        (2, 8) => transcode_as::<2, 8, B, O>(&header, input, bitpool, output),
+        _ => Err(FrameDecodeError::SizeBed),
+    }
+}
@@ -0,0 +1,173 @@
+use crate::filter_state::SynthesisFilter;
+use crate::helper;
+use crate::io::{ByteError, ByteInput, ByteOutput};
+use crate::sbc::{Channels, Subbands, ValidChannels, ValidSubbands};
+use crate::sbc::{FILTER_ORDER, WIDE_SHIFT};
+use core::fmt::{Debug, Formatter};
+use core::marker::PhantomData;
+
+/// Largest [`WideFilterState::snapshot_len`] of any configuration.
+pub const MAX_WIDE_SNAPSHOT_LEN: usize = 3 + 2 * 8 * FILTER_ORDER * 8;
+
+/// Output sample of a [`WideFilterState`].
+pub trait WideSample: Copy + Default {
+    fn from_wide(v: i64) -> Self;
+}
+
+/// 24-bit PCM in the low bits of an `i32`.
+impl WideSample for i32 {
+    fn from_wide(v: i64) -> Self {
+        helper::saturating_i24(v >> (WIDE_SHIFT - 8))
+    }
+}
+
+/// Float PCM, where `1.0` is full scale. Not clipped.
+impl WideSample for f32 {
+    fn from_wide(v: i64) -> Self {
+        (v as f64 / (1_u64 << (WIDE_SHIFT + 15)) as f64) as f32
+    }
+}
+
+/// Synthesis filter with 64-bit accumulation and history.
+#[derive(Copy, Clone)]
+pub struct WideFilterState<const CHANNELS: usize, const SUBBANDS: usize, S: WideSample = i32>
+where
+    Channels<CHANNELS>: ValidChannels,
+    Subbands<SUBBANDS>: ValidSubbands,
+{
+    filter_state: [[[i64; FILTER_ORDER]; SUBBANDS]; CHANNELS],
+    step: u8,
+    sample: PhantomData<S>,
+}
+
+impl<const CHANNELS: usize, const SUBBANDS: usize, S: WideSample>
+    WideFilterState<CHANNELS, SUBBANDS, S>
+where
+    Channels<CHANNELS>: ValidChannels,
+    Subbands<SUBBANDS>: ValidSubbands,
+{
+    pub const fn new() -> Self {
+        Self {
+            filter_state: [[[0; FILTER_ORDER]; SUBBANDS]; CHANNELS],
+            step: 0,
+            sample: PhantomData,
+        }
+    }
+
+    /// Clears the history, as if nothing had been decoded yet.
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    /// Length in bytes of [`WideFilterState::snapshot`].
+    pub const fn snapshot_len() -> usize {
+        3 + CHANNELS * SUBBANDS * FILTER_ORDER * 8
+    }
+
+    /// Writes the state like [`FilterState::snapshot`](crate::filter_state::FilterState::snapshot),
+    /// with the history as little-endian `i64`. The output format is not part of it, so a
+    /// snapshot of the 24-bit filter can be restored as the float one.
+    pub fn snapshot<O: ByteOutput>(&self, output: &mut O) -> Result<(), ByteError> {
+        if output.remaining().is_some_and(|n| n < Self::snapshot_len()) {
+            return Err(());
+        }
+        output.write(&[CHANNELS as u8, SUBBANDS as u8, self.step])?;
+        for ch in 0..CHANNELS {
+            for sb in 0..SUBBANDS {
+                for v in self.filter_state[ch][sb] {
+                    output.write(&v.to_le_bytes())?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads a state written by [`WideFilterState::snapshot`] for the same configuration.
+    pub fn restore<B: ByteInput>(input: &mut B) -> Result<Self, ByteError> {
+        let mut head = [0; 3];
+        input.read(&mut head)?;
+        if head[0] as usize != CHANNELS
+            || head[1] as usize != SUBBANDS
+            || head[2] as usize >= FILTER_ORDER
+        {
+            return Err(());
+        }
+        let mut state = Self::new();
+        state.step = head[2];
+        for ch in 0..CHANNELS {
+            for sb in 0..SUBBANDS {
+                for v in state.filter_state[ch][sb].iter_mut() {
+                    let mut b = [0; 8];
+                    input.read(&mut b)?;
+                    *v = i64::from_le_bytes(b);
+                }
+            }
+        }
+        Ok(state)
+    }
+
+    pub fn filter(&mut self, s: &[[i32; SUBBANDS]; CHANNELS]) -> [[S; SUBBANDS]; CHANNELS] {
+        let step = self.step as usize;
+
+        let mut o = [[S::default(); SUBBANDS]; CHANNELS];
+        for ch in 0..CHANNELS {
+            let mut w = [0; SUBBANDS];
+            <Subbands<SUBBANDS> as ValidSubbands>::decode64(
+                step,
+                &mut self.filter_state[ch],
+                &s[ch],
+                &mut w,
+            );
+            for sb in 0..SUBBANDS {
+                o[ch][sb] = S::from_wide(w[sb]);
+            }
+        }
+
+        if step + 1 == FILTER_ORDER {
+            self.step = 0;
+        } else {
+            self.step = (step + 1) as u8;
+        }
+        o
+    }
+}
+
+impl<const CHANNELS: usize, const SUBBANDS: usize, S: WideSample>
+    SynthesisFilter<CHANNELS, SUBBANDS> for WideFilterState<CHANNELS, SUBBANDS, S>
+where
+    Channels<CHANNELS>: ValidChannels,
+    Subbands<SUBBANDS>: ValidSubbands,
+{
+    type Sample = S;
+
+    fn filter(&mut self, s: &[[i32; SUBBANDS]; CHANNELS]) -> [[S; SUBBANDS]; CHANNELS] {
+        WideFilterState::filter(self, s)
+    }
+}
+
+impl<const CHANNELS: usize, const SUBBANDS: usize, S: WideSample> Default
+    for WideFilterState<CHANNELS, SUBBANDS, S>
+where
+    Channels<CHANNELS>: ValidChannels,
+    Subbands<SUBBANDS>: ValidSubbands,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const CHANNELS: usize, const SUBBANDS: usize, S: WideSample> Debug
+    for WideFilterState<CHANNELS, SUBBANDS, S>
+where
+    Channels<CHANNELS>: ValidChannels,
+    Subbands<SUBBANDS>: ValidSubbands,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        let mut s = f.debug_struct("WideFilterState");
+        s.field("CHANNELS", &CHANNELS);
+        s.field("SUBBANDS", &SUBBANDS);
+        s.field("filter_state", &self.filter_state);
+        s.field("step", &self.step);
+        s.finish()
+    }
+}
@@ -0,0 +1,511 @@
+#!/usr/bin/env python3
+"""Floating point SBC encoder and decoder written from the A2DP specification, used to make
+the test vectors in this directory.
+
+    python3 testdata/sbc_reference.py
+
+encodes each vector in VECTORS to `<name>.sbc` and decodes it back to `<name>.pcm`, and
+decodes `DATA` from src/test.rs to `data.pcm`. PCM is interleaved signed 16 bit little
+endian. The vectors in WIDE_VECTORS are also decoded without rounding to `<name>.f32`,
+interleaved 32 bit little endian floats where 1.0 is full scale. `bit_allocation.txt` holds
+the allocation of random scale factors for every mode, allocation method, sampling frequency
+and subband count, as the expected values of `test_bit_allocation`.
+
+The expected PCM is what this script decodes, so the vectors show that the crate agrees with
+a second implementation of the specification by the same authors, not with a third-party
+decoder. They have not been checked against one yet. To do that, install `sbcdec` from
+libsbc (sbc-tools) and run
+
+    python3 testdata/sbc_reference.py --sbcdec
+
+which decodes every `.sbc` vector and `DATA` with `sbcdec -f <out>.au <name>.sbc`, adding
+`-m` for mSBC, and fails where its output is more than 4 LSB away from the `.pcm` here.
+"""
+
+import math
+import os
+import random
+import re
+import struct
+import subprocess
+import sys
+import tempfile
+
+HERE = os.path.dirname(os.path.abspath(__file__))
+
+# Prototype filter coefficients, tables 12.23 and 12.24 of the specification.
+PROTO_4 = [
+    +0.00000000E+00, +5.36548976E-04, +1.49188357E-03, +2.73370904E-03,
+    +3.83720193E-03, +3.89205149E-03, +1.86581691E-03, -3.06012286E-03,
+    +1.09137620E-02, +2.04385087E-02, +2.88757392E-02, +3.21939290E-02,
+    +2.58767811E-02, +6.13245186E-03, -2.88217274E-02, -7.76463494E-02,
+    +1.35593274E-01, +1.94987841E-01, +2.46636662E-01, +2.81828203E-01,
+    +2.94315332E-01, +2.81828203E-01, +2.46636662E-01, +1.94987841E-01,
+    -1.35593274E-01, -7.76463494E-02, -2.88217274E-02, +6.13245186E-03,
+    +2.58767811E-02, +3.21939290E-02, +2.88757392E-02, +2.04385087E-02,
+    -1.09137620E-02, -3.06012286E-03, +1.86581691E-03, +3.89205149E-03,
+    +3.83720193E-03, +2.73370904E-03, +1.49188357E-03, +5.36548976E-04,
+]
+PROTO_8 = [
+    +0.00000000E+00, +1.56575398E-04, +3.43256425E-04, +5.54620202E-04,
+    +8.23919506E-04, +1.13992507E-03, +1.47640169E-03, +1.78371725E-03,
+    +2.01182542E-03, +2.10371989E-03, +1.99454554E-03, +1.61656283E-03,
+    +9.02154502E-04, -1.78805361E-04, -1.64973098E-03, -3.49717454E-03,
+    +5.65949473E-03, +8.02941163E-03, +1.04584443E-02, +1.27472335E-02,
+    +1.46525263E-02, +1.59045603E-02, +1.62208471E-02, +1.53184106E-02,
+    +1.29371806E-02, +8.85757540E-03, +2.92408442E-03, -4.91578024E-03,
+    -1.46404076E-02, -2.61098752E-02, -3.90751381E-02, -5.31873032E-02,
+    +6.79989431E-02, +8.29847578E-02, +9.75753918E-02, +1.11196689E-01,
+    +1.23264548E-01, +1.33264415E-01, +1.40753505E-01, +1.45389847E-01,
+    +1.46955068E-01, +1.45389847E-01, +1.40753505E-01, +1.33264415E-01,
+    +1.23264548E-01, +1.11196689E-01, +9.75753918E-02, +8.29847578E-02,
+    -6.79989431E-02, -5.31873032E-02, -3.90751381E-02, -2.61098752E-02,
+    -1.46404076E-02, -4.91578024E-03, +2.92408442E-03, +8.85757540E-03,
+    +1.29371806E-02, +1.53184106E-02, +1.62208471E-02, +1.59045603E-02,
+    +1.46525263E-02, +1.27472335E-02, +1.04584443E-02, +8.02941163E-03,
+    -5.65949473E-03, -3.49717454E-03, -1.64973098E-03, -1.78805361E-04,
+    +9.02154502E-04, +1.61656283E-03, +1.99454554E-03, +2.10371989E-03,
+    +2.01182542E-03, +1.78371725E-03, +1.47640169E-03, +1.13992507E-03,
+    +8.23919506E-04, +5.54620202E-04, +3.43256425E-04, +1.56575398E-04,
+]
+
+FREQUENCIES = [16000, 32000, 44100, 48000]
+BLOCKS = [4, 8, 12, 16]
+MONO, DUAL_CHANNEL, STEREO, JOINT_STEREO = range(4)
+LOUDNESS, SNR = range(2)
+MSBC = "msbc"
+
+OFFSET_4 = [[-1, 0, 0, 0], [-2, 0, 0, 1], [-2, 0, 0, 1], [-2, 0, 0, 1]]
+OFFSET_8 = [
+    [-2, 0, 0, 0, 0, 0, 0, 1],
+    [-3, 0, 0, 0, 0, 0, 1, 2],
+    [-4, 0, 0, 0, 0, 0, 1, 2],
+    [-4, 0, 0, 0, 0, 0, 1, 2],
+]
+
+
+class Config:
+    def __init__(self, frequency, blocks, mode, allocation, subbands, bitpool, msbc=False):
+        self.frequency = frequency
+        self.blocks = blocks
+        self.mode = mode
+        self.allocation = allocation
+        self.subbands = subbands
+        self.bitpool = bitpool
+        self.msbc = msbc
+        self.channels = 1 if mode == MONO else 2
+
+    def header(self):
+        if self.msbc:
+            return bytes([0xAD, 0, 0])
+        return bytes([
+            0x9C,
+            FREQUENCIES.index(self.frequency) << 6
+            | BLOCKS.index(self.blocks) << 4
+            | self.mode << 2
+            | self.allocation << 1
+            | (self.subbands == 8),
+            self.bitpool,
+        ])
+
+
+def parse_header(data):
+    if data[0] == 0xAD:
+        return Config(16000, 15, MONO, LOUDNESS, 8, 26, msbc=True)
+    if data[0] != 0x9C:
+        raise ValueError("bad syncword")
+    b = data[1]
+    return Config(
+        FREQUENCIES[b >> 6],
+        BLOCKS[b >> 4 & 3],
+        b >> 2 & 3,
+        b >> 1 & 1,
+        8 if b & 1 else 4,
+        data[2],
+    )
+
+
+def crc8(bits):
+    crc = 0x0F
+    for bit in bits:
+        top = crc >> 7 ^ bit
+        crc = crc << 1 & 0xFF
+        if top:
+            crc ^= 0x1D
+    return crc
+
+
+def to_bits(value, n):
+    return [value >> (n - 1 - i) & 1 for i in range(n)]
+
+
+def bit_allocation(c, scale_factor, bitslices=None):
+    """Bits per channel and subband, section 12.6.3. The final bitslice of each channel is
+    appended to `bitslices` if given."""
+    m = c.subbands
+    offset = (OFFSET_4 if m == 4 else OFFSET_8)[FREQUENCIES.index(c.frequency)]
+
+    def bitneed(ch):
+        need = []
+        for sb in range(m):
+            sf = scale_factor[ch][sb]
+            if c.allocation == SNR:
+                need.append(sf)
+            elif sf == 0:
+                need.append(-5)
+            else:
+                loudness = sf - offset[sb]
+                need.append(loudness // 2 if loudness > 0 else loudness)
+        return need
+
+    def allocate(need):
+        # `need` lists (channel, subband, bitneed) in the order bits are handed out.
+        bits = {(ch, sb): 0 for ch, sb, _ in need}
+        max_bitneed = max(n for _, _, n in need)
+        bitcount, slicecount, bitslice = 0, 0, max_bitneed + 1
+        while True:
+            bitslice -= 1
+            bitcount += slicecount
+            slicecount = 0
+            for _, _, n in need:
+                if bitslice + 1 < n < bitslice + 16:
+                    slicecount += 1
+                elif n == bitslice + 1:
+                    slicecount += 2
+            if bitcount + slicecount >= c.bitpool:
+                break
+        if bitcount + slicecount == c.bitpool:
+            bitcount += slicecount
+            bitslice -= 1
+        for ch, sb, n in need:
+            bits[ch, sb] = 0 if n < bitslice + 2 else min(n - bitslice, 16)
+        for ch, sb, n in need:
+            if bitcount >= c.bitpool:
+                break
+            if 2 <= bits[ch, sb] < 16:
+                bits[ch, sb] += 1
+                bitcount += 1
+            elif n == bitslice + 1 and c.bitpool > bitcount + 1:
+                bits[ch, sb] = 2
+                bitcount += 2
+        for ch, sb, _ in need:
+            if bitcount >= c.bitpool:
+                break
+            if bits[ch, sb] < 16:
+                bits[ch, sb] += 1
+                bitcount += 1
+        if bitslices is not None:
+            bitslices.extend([bitslice] * len({ch for ch, _, _ in need}))
+        return bits
+
+    if c.mode in (MONO, DUAL_CHANNEL):
+        bits = {}
+        for ch in range(c.channels):
+            need = bitneed(ch)
+            bits.update(allocate([(ch, sb, need[sb]) for sb in range(m)]))
+    else:
+        need = [bitneed(0), bitneed(1)]
+        bits = allocate([(ch, sb, need[ch][sb]) for sb in range(m) for ch in range(2)])
+    return [[bits[ch, sb] for sb in range(m)] for ch in range(c.channels)]
+
+
+class Analysis:
+    """Section 12.5.1, for one channel."""
+
+    def __init__(self, m):
+        self.m = m
+        self.c = PROTO_4 if m == 4 else PROTO_8
+        self.x = [0.0] * (10 * m)
+
+    def filter(self, pcm):
+        m = self.m
+        self.x = [0.0] * m + self.x[: 9 * m]
+        for i in range(m):
+            self.x[m - 1 - i] = pcm[i]
+        z = [self.c[i] * self.x[i] for i in range(10 * m)]
+        y = [sum(z[i + 2 * m * k] for k in range(5)) for i in range(2 * m)]
+        return [
+            sum(math.cos((i + 0.5) * (k - m / 2) * math.pi / m) * y[k] for k in range(2 * m))
+            for i in range(m)
+        ]
+
+
+class Synthesis:
+    """Section 12.6.4, for one channel."""
+
+    def __init__(self, m):
+        self.m = m
+        self.d = [-m * v for v in (PROTO_4 if m == 4 else PROTO_8)]
+        self.v = [0.0] * (20 * m)
+
+    def filter(self, s):
+        m = self.m
+        self.v = [0.0] * (2 * m) + self.v[: 18 * m]
+        for k in range(2 * m):
+            self.v[k] = sum(
+                math.cos((i + 0.5) * (k + m / 2) * math.pi / m) * s[i] for i in range(m)
+            )
+        u = [0.0] * (10 * m)
+        for i in range(5):
+            for j in range(m):
+                u[2 * m * i + j] = self.v[4 * m * i + j]
+                u[2 * m * i + m + j] = self.v[4 * m * i + 3 * m + j]
+        w = [self.d[i] * u[i] for i in range(10 * m)]
+        return [sum(w[j + m * i] for i in range(10)) for j in range(m)]
+
+
+def scale_factor(peak):
+    sf = 0
+    while sf < 15 and peak >= 2 ** (sf + 1):
+        sf += 1
+    return sf
+
+
+def encode(c, pcm):
+    """Encodes interleaved `pcm`, a list of per channel sample lists, frame by frame."""
+    m, n = c.subbands, c.channels
+    analysis = [Analysis(m) for _ in range(n)]
+    frame_samples = c.blocks * m
+    out = bytearray()
+    for start in range(0, len(pcm) - frame_samples + 1, frame_samples):
+        frame = pcm[start : start + frame_samples]
+        sb = [
+            [analysis[ch].filter([frame[b * m + i][ch] for i in range(m)]) for ch in range(n)]
+            for b in range(c.blocks)
+        ]
+        join = [0] * m
+        if c.mode == JOINT_STEREO:
+            for k in range(m - 1):
+                l = [s[0][k] for s in sb]
+                r = [s[1][k] for s in sb]
+                mid = [(a + b) / 2 for a, b in zip(l, r)]
+                side = [(a - b) / 2 for a, b in zip(l, r)]
+                peaks = [max(abs(v) for v in x) for x in (l, r, mid, side)]
+                sfs = [scale_factor(p) for p in peaks]
+                if sfs[2] + sfs[3] < sfs[0] + sfs[1]:
+                    join[k] = 1
+                    for s, a, b in zip(sb, mid, side):
+                        s[0][k], s[1][k] = a, b
+        sf = [
+            [scale_factor(max(abs(s[ch][k]) for s in sb)) for k in range(m)] for ch in range(n)
+        ]
+        bits = bit_allocation(c, sf)
+        header = c.header()
+        crc_bits = to_bits(header[1], 8) + to_bits(header[2], 8)
+        body = []
+        if c.mode == JOINT_STEREO:
+            body += join
+        for ch in range(n):
+            for k in range(m):
+                body += to_bits(sf[ch][k], 4)
+        crc = crc8(crc_bits + body)
+        for s in sb:
+            for ch in range(n):
+                for k in range(m):
+                    nb = bits[ch][k]
+                    if nb == 0:
+                        continue
+                    levels = 2**nb - 1
+                    scale = 2.0 ** (sf[ch][k] + 1)
+                    q = math.floor((s[ch][k] / scale + 1) * levels / 2)
+                    body += to_bits(min(max(q, 0), levels - 1), nb)
+        body += [0] * (-len(body) % 8)
+        out += header + bytes([crc])
+        out += bytes(int("".join(map(str, body[i : i + 8])), 2) for i in range(0, len(body), 8))
+    return bytes(out)
+
+
+def decode(data, rounded=True):
+    """Decodes a stream of frames, checking their CRCs, to interleaved PCM, or to unrounded
+    samples scaled to 16 bits."""
+    pos, pcm, synthesis = 0, [], None
+    while pos < len(data):
+        c = parse_header(data[pos : pos + 3])
+        m, n = c.subbands, c.channels
+        if synthesis is None:
+            synthesis = [Synthesis(m) for _ in range(n)]
+        crc = data[pos + 3]
+        bits_in = []
+        for b in data[pos + 4 :]:
+            bits_in += to_bits(b, 8)
+        at = 0
+
+        def read(count):
+            nonlocal at
+            v = 0
+            for bit in bits_in[at : at + count]:
+                v = v << 1 | bit
+            at += count
+            return v
+
+        join = [read(1) for _ in range(m)] if c.mode == JOINT_STEREO else [0] * m
+        sf = [[read(4) for _ in range(m)] for _ in range(n)]
+        crc_bits = to_bits(data[pos + 1], 8) + to_bits(data[pos + 2], 8) + bits_in[:at]
+        if crc8(crc_bits) != crc:
+            raise ValueError("bad crc at byte %d" % pos)
+        bits = bit_allocation(c, sf)
+        for _ in range(c.blocks):
+            s = [[0.0] * m for _ in range(n)]
+            for ch in range(n):
+                for k in range(m):
+                    nb = bits[ch][k]
+                    if nb:
+                        q = read(nb)
+                        s[ch][k] = 2.0 ** (sf[ch][k] + 1) * ((2 * q + 1) / (2**nb - 1) - 1)
+            for k in range(m):
+                if join[k]:
+                    s[0][k], s[1][k] = s[0][k] + s[1][k], s[0][k] - s[1][k]
+            out = [synthesis[ch].filter(s[ch]) for ch in range(n)]
+            for i in range(m):
+                if rounded:
+                    pcm.append(
+                        [min(max(math.floor(out[ch][i] + 0.5), -32768), 32767) for ch in range(n)]
+                    )
+                else:
+                    pcm.append([out[ch][i] for ch in range(n)])
+        pos += 4 + (at + 7) // 8
+    return pcm
+
+
+def signal(c, frames, gain=1.0):
+    """A few tones per channel over a little noise. The second channel shares most of the
+    first so that joint stereo has something to do."""
+    seed = 1
+    pcm = []
+    for i in range(frames * c.blocks * c.subbands):
+        t = i / c.frequency
+        seed = (seed * 1103515245 + 12345) & 0xFFFFFFFF
+        noise = ((seed >> 16) & 0xFFFF) / 65536 - 0.5
+        left = 0.3 * math.sin(2 * math.pi * 440 * t) + 0.1 * math.sin(2 * math.pi * 3150 * t)
+        right = 0.6 * left + 0.2 * math.sin(2 * math.pi * 660 * t + 1)
+        left += 0.02 * noise
+        pcm.append([round(gain * v * 32767) for v in (left, right)][: c.channels])
+    return pcm
+
+
+VECTORS = [
+    ("mono_b4_sb8", Config(16000, 4, MONO, LOUDNESS, 8, 20)),
+    ("dual_b8_sb4", Config(32000, 8, DUAL_CHANNEL, SNR, 4, 18)),
+    ("stereo_b16_sb8", Config(44100, 16, STEREO, LOUDNESS, 8, 35)),
+    ("joint_b12_sb8", Config(48000, 12, JOINT_STEREO, LOUDNESS, 8, 53)),
+    ("joint_b16_sb4", Config(44100, 16, JOINT_STEREO, SNR, 4, 30)),
+    ("msbc", Config(16000, 15, MONO, LOUDNESS, 8, 26, msbc=True)),
+]
+
+# Near full scale at the highest dual channel bitpool, for the 24-bit and float outputs.
+WIDE_VECTORS = [
+    ("dual_b16_sb8_hifi", Config(48000, 16, DUAL_CHANNEL, LOUDNESS, 8, 128), 2.1),
+]
+
+
+def max_bitpool(c):
+    if c.mode in (MONO, DUAL_CHANNEL):
+        return 16 * c.subbands
+    return 255 if c.subbands == 8 else 32 * c.subbands
+
+
+def allocation_cases():
+    """Lines of `bit_allocation.txt`: the second and third header bytes, the scale factors,
+    the bits and the final bitslices, each group of numbers separated by `|`."""
+    rng = random.Random(30)
+    scale_factors = [
+        lambda: rng.randint(0, 15),
+        lambda: rng.choice([0, 0, rng.randint(1, 15)]),
+        lambda: rng.randint(0, 3),
+        lambda: rng.randint(10, 15),
+    ]
+    for frequency in FREQUENCIES:
+        for mode in range(4):
+            for allocation in (LOUDNESS, SNR):
+                for m in (4, 8):
+                    c = Config(frequency, 16, mode, allocation, m, 0)
+                    top = max_bitpool(c)
+                    for i in range(8):
+                        c.bitpool = [2, top][i] if i < 2 else rng.randint(2, top >> i % 2 * 2)
+                        sf = [[scale_factors[i % 4]() for _ in range(m)] for _ in range(c.channels)]
+                        bitslices = []
+                        bits = bit_allocation(c, sf, bitslices)
+                        yield " | ".join(
+                            " ".join(str(v) for v in group)
+                            for group in (c.header()[1:], sum(sf, []), sum(bits, []), bitslices)
+                        )
+
+
+def write_pcm(path, pcm):
+    with open(path, "wb") as f:
+        for sample in pcm:
+            f.write(struct.pack("<%dh" % len(sample), *sample))
+
+
+def read_pcm(path):
+    with open(path, "rb") as f:
+        data = f.read()
+    return struct.unpack("<%dh" % (len(data) // 2), data)
+
+
+def read_au(path):
+    """Samples of a 16 bit Sun .au file as written by `sbcdec`, or of raw native PCM."""
+    with open(path, "rb") as f:
+        data = f.read()
+    if data[:4] == b".snd":
+        offset = struct.unpack(">I", data[4:8])[0]
+        data = data[offset:]
+        return struct.unpack(">%dh" % (len(data) // 2), data)
+    return struct.unpack("=%dh" % (len(data) // 2), data)
+
+
+def test_data():
+    """The bytes of `DATA` in src/test.rs."""
+    with open(os.path.join(HERE, "..", "src", "test.rs")) as f:
+        source = f.read()
+    data = re.search(r"const DATA: \[u8; \d+\] = \[(.*?)\];", source, re.S).group(1)
+    return bytes(int(v, 16) for v in re.findall(r"0x[0-9A-F]{2}", data))
+
+
+def check_sbcdec():
+    """Decodes the vectors with `sbcdec` and compares its output with the `.pcm` files."""
+    failed = False
+    with tempfile.TemporaryDirectory() as tmp:
+        data = os.path.join(tmp, "data.sbc")
+        with open(data, "wb") as f:
+            f.write(test_data())
+        cases = [(name, os.path.join(HERE, name + ".sbc"), c.msbc) for name, c in VECTORS]
+        cases.append(("data", data, False))
+        for name, stream, msbc in cases:
+            au = os.path.join(tmp, name + ".au")
+            command = ["sbcdec"] + (["-m"] if msbc else []) + ["-f", au, stream]
+            subprocess.run(command, check=True)
+            theirs = read_au(au)
+            ours = read_pcm(os.path.join(HERE, name + ".pcm"))
+            error = max((abs(a - b) for a, b in zip(theirs, ours)), default=0)
+            ok = len(theirs) == len(ours) and error <= 4
+            failed |= not ok
+            print("%-16s %6d samples, max error %d%s" % (name, len(theirs), error, "" if ok else "  FAIL"))
+    sys.exit(1 if failed else 0)
+
+
+def main():
+    for name, c in VECTORS:
+        frames = -(-512 // (c.blocks * c.subbands))
+        stream = encode(c, signal(c, frames))
+        with open(os.path.join(HERE, name + ".sbc"), "wb") as f:
+            f.write(stream)
+        write_pcm(os.path.join(HERE, name + ".pcm"), decode(stream))
+    for name, c, gain in WIDE_VECTORS:
+        frames = -(-512 // (c.blocks * c.subbands))
+        stream = encode(c, signal(c, frames, gain))
+        with open(os.path.join(HERE, name + ".sbc"), "wb") as f:
+            f.write(stream)
+        with open(os.path.join(HERE, name + ".f32"), "wb") as f:
+            for sample in decode(stream, rounded=False):
+                f.write(struct.pack("<%df" % len(sample), *(v / 32768 for v in sample)))
+    with open(os.path.join(HERE, "bit_allocation.txt"), "w") as f:
+        f.writelines(line + "\n" for line in allocation_cases())
+    write_pcm(os.path.join(HERE, "data.pcm"), decode(test_data()))
+
+
+if __name__ == "__main__":
+    if sys.argv[1:] == ["--sbcdec"]:
+        check_sbcdec()
+    else:
+        main()